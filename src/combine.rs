@@ -0,0 +1,405 @@
+use std::convert::TryFrom;
+use std::fs::{read, write};
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::extract::{find_motion, MotionFormat, SEF_MOTION_PHOTO_DATA, SEF_MOTION_PHOTO_NAME};
+use crate::jpeg;
+
+/// The version Samsung's own camera writes into the trailer directory.
+const SEF_VERSION: u32 = 106;
+
+const MOTION_NAMESPACES: [(&str, &str); 3] = [
+    ("GCamera", "http://ns.google.com/photos/1.0/camera/"),
+    ("Container", "http://ns.google.com/photos/1.0/container/"),
+    ("Item", "http://ns.google.com/photos/1.0/container/item/"),
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The inverse of `extract::extract_mp4`: writes `still` with `video` embedded as a motion photo
+/// in the given format. Any video the still already carried is replaced.
+pub fn combine_files(
+    still: impl AsRef<Path>,
+    video: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    format: MotionFormat,
+) -> io::Result<()> {
+    let combined = combine(&read(still)?, &read(video)?, format, None)?;
+    write(output, combined)
+}
+
+/// Writes the motion photo at `photo` to `output`, converted into `format`. Nothing is written
+/// if it can't be converted.
+pub fn convert_files(
+    photo: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    format: MotionFormat,
+) -> io::Result<()> {
+    let converted = convert(&read(photo)?, format)?;
+    write(output, converted)
+}
+
+/// Rewrites the motion photo at `path` into `format` in place.
+pub fn convert_file(path: impl AsRef<Path>, format: MotionFormat) -> io::Result<()> {
+    let path = path.as_ref();
+    let converted = convert(&read(path)?, format)?;
+    write(path, converted)
+}
+
+/// Moves the video of a motion photo into the layout of `format`. The still, including its
+/// EXIF, stays as it is, and so does the frame it was marked to show where possible. Samsung's
+/// trailer only survives as far as the video goes, its other entries are dropped.
+pub fn convert(buf: &[u8], format: MotionFormat) -> io::Result<Vec<u8>> {
+    let motion = find_motion(buf).ok_or_else(|| invalid("The photo has no motion data"))?;
+    if motion.format == format {
+        return Ok(buf.to_vec());
+    }
+    // Whatever follows the video, like the rest of a trailer it was found in, isn't part of it
+    let video = motion.video(buf);
+    let video = &video[..crate::mp4::validate(video)?];
+    combine(buf, video, format, motion.presentation_timestamp_us)
+}
+
+/// Only the Google layout records which frame the still shows, as `timestamp_us`.
+pub fn combine(
+    still: &[u8],
+    video: &[u8],
+    format: MotionFormat,
+    timestamp_us: Option<i64>,
+) -> io::Result<Vec<u8>> {
+    let image_len = jpeg::image_len(still).ok_or_else(|| invalid("The still is not a JPEG"))?;
+    if video.get(4..8) != Some(&b"ftyp"[..]) {
+        return Err(invalid("The video is not an MP4"));
+    }
+    let image = &still[..image_len];
+
+    match format {
+        MotionFormat::Samsung => {
+            // A Google description left behind would point readers at the wrong bytes
+            let mut combined = without_motion_xmp(image)?;
+            combined.extend_from_slice(&samsung_trailer(video)?);
+            Ok(combined)
+        }
+        MotionFormat::Google => {
+            let mut combined = with_motion_xmp(image, video.len(), timestamp_us)?;
+            combined.extend_from_slice(video);
+            Ok(combined)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't write {} motion photos", format),
+        )),
+    }
+}
+
+/// Samsung appends the video as an entry of its trailer, followed by a directory of entries.
+fn samsung_trailer(video: &[u8]) -> io::Result<Vec<u8>> {
+    let mut trailer = Vec::with_capacity(video.len() + 64);
+    trailer.extend_from_slice(&0u16.to_le_bytes());
+    trailer.extend_from_slice(&SEF_MOTION_PHOTO_DATA.to_le_bytes());
+    trailer.extend_from_slice(&(SEF_MOTION_PHOTO_NAME.len() as u32).to_le_bytes());
+    trailer.extend_from_slice(SEF_MOTION_PHOTO_NAME);
+    trailer.extend_from_slice(video);
+    let entry_len = u32::try_from(trailer.len()).map_err(|_| invalid("The video is too large"))?;
+
+    // The entry is located by its distance back from the start of the directory
+    let directory = trailer.len();
+    trailer.extend_from_slice(b"SEFH");
+    trailer.extend_from_slice(&SEF_VERSION.to_le_bytes());
+    trailer.extend_from_slice(&1u32.to_le_bytes());
+    trailer.extend_from_slice(&0u16.to_le_bytes());
+    trailer.extend_from_slice(&SEF_MOTION_PHOTO_DATA.to_le_bytes());
+    trailer.extend_from_slice(&entry_len.to_le_bytes());
+    trailer.extend_from_slice(&entry_len.to_le_bytes());
+    let directory_len = (trailer.len() - directory) as u32;
+    trailer.extend_from_slice(&directory_len.to_le_bytes());
+    trailer.extend_from_slice(b"SEFT");
+    Ok(trailer)
+}
+
+fn is_motion_xmp(xmp: &[u8]) -> bool {
+    let xmp = String::from_utf8_lossy(xmp);
+    xmp.contains("GCamera:MicroVideo") || xmp.contains("GCamera:MotionPhoto")
+}
+
+/// Removes the motion photo tags and container directory from an XMP packet, leaving the rest
+/// of it as it was.
+fn strip_motion_xmp(xmp: &str) -> String {
+    let attribute = Regex::new(r#"\s+GCamera:(?:MotionPhoto|MicroVideo)\w*="[^"]*""#).unwrap();
+    let element = Regex::new(
+        r#"\s*<GCamera:(?:MotionPhoto|MicroVideo)\w*>[^<]*</GCamera:(?:MotionPhoto|MicroVideo)\w*>"#,
+    )
+    .unwrap();
+    let directory = Regex::new(r#"(?s)\s*<Container:Directory>.*?</Container:Directory>"#).unwrap();
+    let xmp = attribute.replace_all(xmp, "");
+    let xmp = element.replace_all(&xmp, "");
+    directory.replace_all(&xmp, "").into_owned()
+}
+
+fn without_motion_xmp(image: &[u8]) -> io::Result<Vec<u8>> {
+    let segments = jpeg::segments(image).ok_or_else(|| invalid("The still is not a JPEG"))?;
+    let mut stripped = Vec::with_capacity(image.len());
+    let mut pos = 0;
+    for segment in segments.iter().filter(|segment| is_xmp(image, segment)) {
+        let xmp = &image[segment.payload()][jpeg::XMP_NAMESPACE.len()..];
+        if !is_motion_xmp(xmp) {
+            continue;
+        }
+        let xmp = strip_motion_xmp(&String::from_utf8_lossy(xmp));
+        stripped.extend_from_slice(&image[pos..segment.start]);
+        stripped.extend(
+            jpeg::app1_segment(jpeg::XMP_NAMESPACE, xmp.as_bytes())
+                .ok_or_else(|| invalid("The XMP is too large"))?,
+        );
+        pos = segment.end;
+    }
+    stripped.extend_from_slice(&image[pos..]);
+    Ok(stripped)
+}
+
+fn is_xmp(image: &[u8], segment: &jpeg::Segment) -> bool {
+    segment.marker == jpeg::APP1 && image[segment.payload()].starts_with(jpeg::XMP_NAMESPACE)
+}
+
+fn motion_attributes(video_len: usize, timestamp_us: Option<i64>) -> String {
+    format!(
+        r#"
+      GCamera:MotionPhoto="1"
+      GCamera:MotionPhotoVersion="1"
+      GCamera:MotionPhotoPresentationTimestampUs="{timestamp}"
+      GCamera:MicroVideo="1"
+      GCamera:MicroVideoVersion="1"
+      GCamera:MicroVideoOffset="{len}"
+      GCamera:MicroVideoPresentationTimestampUs="{timestamp}""#,
+        len = video_len,
+        timestamp = timestamp_us.unwrap_or(-1)
+    )
+}
+
+fn motion_directory(video_len: usize) -> String {
+    format!(
+        r#"
+      <Container:Directory>
+        <rdf:Seq>
+          <rdf:li rdf:parseType="Resource">
+            <Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary" Item:Length="0" Item:Padding="0"/>
+          </rdf:li>
+          <rdf:li rdf:parseType="Resource">
+            <Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="{len}" Item:Padding="0"/>
+          </rdf:li>
+        </rdf:Seq>
+      </Container:Directory>"#,
+        len = video_len
+    )
+}
+
+fn namespaces(declared: &str) -> String {
+    MOTION_NAMESPACES
+        .iter()
+        .filter(|(prefix, _)| !declared.contains(&format!("xmlns:{}=", prefix)))
+        .map(|(prefix, uri)| format!("\n        xmlns:{}=\"{}\"", prefix, uri))
+        .collect()
+}
+
+fn motion_xmp(video_len: usize, timestamp_us: Option<i64>) -> String {
+    format!(
+        r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="MotionSplit">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""{namespaces}{attributes}>{directory}
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>"#,
+        namespaces = namespaces(""),
+        attributes = motion_attributes(video_len, timestamp_us),
+        directory = motion_directory(video_len)
+    )
+}
+
+/// Adds the Google motion photo description to the first `rdf:Description` of an existing XMP
+/// packet, in place of any motion tags it had. Everything else in the packet is kept.
+fn merge_motion_xmp(xmp: &str, video_len: usize, timestamp_us: Option<i64>) -> Option<String> {
+    let xmp = strip_motion_xmp(xmp);
+    let description = Regex::new(r#"<rdf:Description\b[^>]*?(/?)>"#).unwrap();
+    let captures = description.captures(&xmp)?;
+    let tag = captures.get(0)?;
+    let self_closing = !captures[1].is_empty();
+    let attributes_end = tag.end() - if self_closing { 2 } else { 1 };
+
+    let mut merged = String::with_capacity(xmp.len() + 2048);
+    merged.push_str(&xmp[..attributes_end]);
+    merged.push_str(&namespaces(tag.as_str()));
+    merged.push_str(&motion_attributes(video_len, timestamp_us));
+    merged.push('>');
+    merged.push_str(&motion_directory(video_len));
+    if self_closing {
+        merged.push_str("</rdf:Description>");
+    }
+    merged.push_str(&xmp[tag.end()..]);
+    Some(merged)
+}
+
+/// Adds the Google motion photo description to the XMP of `image`, or gives it one. The segment
+/// goes after the leading APP0/APP1 segments, so the EXIF stays where readers expect it.
+fn with_motion_xmp(
+    image: &[u8],
+    video_len: usize,
+    timestamp_us: Option<i64>,
+) -> io::Result<Vec<u8>> {
+    let segments = jpeg::segments(image).ok_or_else(|| invalid("The still is not a JPEG"))?;
+    let xmp = jpeg::xmp(image)
+        .and_then(|xmp| merge_motion_xmp(&String::from_utf8_lossy(xmp), video_len, timestamp_us))
+        .unwrap_or_else(|| motion_xmp(video_len, timestamp_us));
+    let xmp = jpeg::app1_segment(jpeg::XMP_NAMESPACE, xmp.as_bytes())
+        .ok_or_else(|| invalid("The XMP is too large"))?;
+
+    let mut kept = segments
+        .iter()
+        .filter(|segment| !is_xmp(image, segment))
+        .peekable();
+
+    let mut combined = Vec::with_capacity(image.len() + xmp.len());
+    combined.extend_from_slice(&image[..2]);
+    while let Some(segment) =
+        kept.next_if(|segment| segment.marker == 0xE0 || segment.marker == jpeg::APP1)
+    {
+        combined.extend_from_slice(&image[segment.start..segment.end]);
+    }
+    combined.extend_from_slice(&xmp);
+    for segment in kept {
+        combined.extend_from_slice(&image[segment.start..segment.end]);
+    }
+    // Segments end at the start of the scan, the image data follows
+    combined.extend_from_slice(&image[segments.last().map_or(2, |segment| segment.end)..]);
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isobmff::bmff;
+
+    fn still(xmp: Option<&str>) -> Vec<u8> {
+        let mut buf = vec![0xFF, 0xD8];
+        buf.extend(jpeg::app1_segment(jpeg::EXIF_HEADER, b"tiff").unwrap());
+        if let Some(xmp) = xmp {
+            buf.extend(jpeg::app1_segment(jpeg::XMP_NAMESPACE, xmp.as_bytes()).unwrap());
+        }
+        buf.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 2, 0xFF, 0xD9]);
+        buf
+    }
+
+    fn video() -> Vec<u8> {
+        let mut buf = bmff(b"ftyp", b"isom\0\0\0\0");
+        buf.extend(bmff(b"moov", &bmff(b"mvhd", &[0; 8])));
+        buf.extend(bmff(b"mdat", b"data"));
+        buf
+    }
+
+    fn xmp_of(buf: &[u8]) -> String {
+        String::from_utf8_lossy(jpeg::xmp(buf).unwrap()).into_owned()
+    }
+
+    const RATED: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="5"/>
+  </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn samsung_trailer_is_found_again() {
+        let combined = combine(&still(None), &video(), MotionFormat::Samsung, None).unwrap();
+        let motion = find_motion(&combined).unwrap();
+        assert_eq!(motion.format, MotionFormat::Samsung);
+        assert_eq!(motion.video(&combined), &video()[..]);
+        assert!(combined.starts_with(&still(None)));
+    }
+
+    #[test]
+    fn google_xmp_keeps_the_rest_of_the_packet() {
+        let combined = combine(
+            &still(Some(RATED)),
+            &video(),
+            MotionFormat::Google,
+            Some(1500),
+        )
+        .unwrap();
+        let motion = find_motion(&combined).unwrap();
+        assert_eq!(motion.format, MotionFormat::Google);
+        assert_eq!(motion.video(&combined), &video()[..]);
+        assert_eq!(motion.presentation_timestamp_us, Some(1500));
+        let xmp = xmp_of(&combined);
+        assert!(xmp.contains(r#"xmp:Rating="5""#));
+        assert_eq!(xmp.matches("<rdf:Description").count(), 1);
+        assert!(xmp.contains("</rdf:Description>"));
+    }
+
+    #[test]
+    fn google_xmp_is_added_when_there_is_none() {
+        let combined = combine(&still(None), &video(), MotionFormat::Google, None).unwrap();
+        assert_eq!(find_motion(&combined).unwrap().format, MotionFormat::Google);
+        // The EXIF stays in front
+        let segments = jpeg::segments(&combined).unwrap();
+        assert!(combined[segments[0].payload()].starts_with(jpeg::EXIF_HEADER));
+    }
+
+    #[test]
+    fn converting_back_and_forth_only_touches_motion_tags() {
+        let google = combine(&still(Some(RATED)), &video(), MotionFormat::Google, None).unwrap();
+        let samsung = convert(&google, MotionFormat::Samsung).unwrap();
+        assert_eq!(find_motion(&samsung).unwrap().format, MotionFormat::Samsung);
+        let xmp = xmp_of(&samsung);
+        assert!(xmp.contains(r#"xmp:Rating="5""#));
+        assert!(!is_motion_xmp(xmp.as_bytes()));
+        assert!(!xmp.contains("Container:Directory"));
+
+        let again = convert(&samsung, MotionFormat::Google).unwrap();
+        assert_eq!(find_motion(&again).unwrap().format, MotionFormat::Google);
+        assert_eq!(xmp_of(&again).matches("GCamera:MotionPhoto=").count(), 1);
+    }
+
+    #[test]
+    fn leaves_the_rest_of_a_samsung_trailer_out() {
+        let mut samsung = still(None);
+        let mut data = SEF_MOTION_PHOTO_NAME.to_vec();
+        data.extend(video());
+        // Without a directory, the video seems to run on into the entries after it
+        data.extend_from_slice(b"\0\0\x01\x0a\x0c\0\0\0Image_UTC_Data1580000000000");
+        samsung.extend(data);
+        assert_eq!(find_motion(&samsung).unwrap().format, MotionFormat::Samsung);
+
+        let google = convert(&samsung, MotionFormat::Google).unwrap();
+        let motion = find_motion(&google).unwrap();
+        assert_eq!(motion.format, MotionFormat::Google);
+        assert_eq!(motion.video(&google), &video()[..]);
+    }
+
+    #[test]
+    fn does_not_convert_broken_videos() {
+        let mut samsung = still(None);
+        samsung.extend_from_slice(SEF_MOTION_PHOTO_NAME);
+        samsung.extend(bmff(b"ftyp", b"isom\0\0\0\0"));
+        samsung.extend(bmff(b"mdat", b"data"));
+        assert!(convert(&samsung, MotionFormat::Google).is_err());
+    }
+
+    #[test]
+    fn strips_motion_elements() {
+        let xmp = "<rdf:Description><GCamera:MicroVideo>1</GCamera:MicroVideo><xmp:Rating>2</xmp:Rating></rdf:Description>";
+        assert_eq!(
+            strip_motion_xmp(xmp),
+            "<rdf:Description><xmp:Rating>2</xmp:Rating></rdf:Description>"
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_combine() {
+        assert!(combine(b"not a jpeg", &video(), MotionFormat::Google, None).is_err());
+        assert!(combine(&still(None), b"not an mp4", MotionFormat::Samsung, None).is_err());
+        assert!(convert(&still(None), MotionFormat::Google).is_err());
+    }
+}
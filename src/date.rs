@@ -0,0 +1,170 @@
+use std::fmt::Display;
+
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use exif::{Exif, In, Tag, Value};
+use serde::Serialize;
+
+/// Where the date of a file was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DateSource {
+    Exif,
+    /// The JSON Google Takeout exports next to a photo
+    Takeout,
+    Container,
+    FileName,
+    /// Entered while reviewing the plan
+    Manual,
+}
+
+impl Display for DateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Exif => "EXIF",
+                Self::Takeout => "Google Takeout",
+                Self::Container => "video container",
+                Self::FileName => "file name",
+                Self::Manual => "entered by hand",
+            }
+        )
+    }
+}
+
+pub fn exif_ascii(exif: &Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().map(Vec::as_slice),
+        _ => None,
+    }
+}
+
+/// The JPEG thumbnail embedded in the EXIF.
+pub fn thumbnail(exif: &Exif) -> Option<&[u8]> {
+    let field = |tag| exif.get_field(tag, In::THUMBNAIL)?.value.get_uint(0);
+    let offset = field(Tag::JPEGInterchangeFormat)? as usize;
+    let len = field(Tag::JPEGInterchangeFormatLength)? as usize;
+    exif.buf().get(offset..offset.checked_add(len)?)
+}
+
+/// The moment a photo was taken according to its EXIF, in the local time of the camera.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalDate {
+    pub local: NaiveDateTime,
+    /// Minutes east of UTC, if the camera recorded it
+    pub offset: Option<i16>,
+}
+
+impl LocalDate {
+    pub fn from_exif(exif: &Exif) -> Option<Self> {
+        let mut date = exif::DateTime::from_ascii(exif_ascii(exif, Tag::DateTimeOriginal)?).ok()?;
+        if let Some(offset) = exif_ascii(exif, Tag::OffsetTimeOriginal) {
+            // A broken offset is no reason to drop the date itself
+            let _ = date.parse_offset(offset);
+        }
+        let local = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+            .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)?;
+        Some(Self {
+            local,
+            offset: date.offset,
+        })
+    }
+
+    /// Without a recorded offset, the local time is the best guess there is.
+    pub fn utc(self) -> NaiveDateTime {
+        match self.offset {
+            Some(minutes) => self.local - Duration::minutes(minutes as i64),
+            None => self.local,
+        }
+    }
+
+    /// Seconds since 1970 for the file system. Without a recorded offset, the time is taken to
+    /// be in the time zone of this computer, as that's where the files are looked at.
+    pub fn timestamp(self) -> Option<i64> {
+        match self.offset {
+            Some(_) => Some(self.utc().timestamp()),
+            None => Local
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map(|time| time.timestamp()),
+        }
+    }
+
+    /// ISO 8601, with the offset if it's known.
+    pub fn to_iso8601(self) -> String {
+        let mut date = self.local.format("%Y-%m-%dT%H:%M:%S").to_string();
+        if let Some(offset) = self.offset {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.abs();
+            date.push_str(&format!("{}{:02}:{:02}", sign, offset / 60, offset % 60));
+        }
+        date
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn finds_the_thumbnail() {
+        use exif::experimental::Writer;
+        use exif::Field;
+
+        let make = Field {
+            tag: Tag::Make,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"Canon".to_vec()]),
+        };
+        let thumb = b"\xFF\xD8thumb\xFF\xD9";
+        let mut writer = Writer::new();
+        writer.push_field(&make);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let exif = exif::Reader::new().read_raw(tiff.into_inner()).unwrap();
+        assert_eq!(thumbnail(&exif), None);
+
+        writer.set_jpeg(thumb, In::THUMBNAIL);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let exif = exif::Reader::new().read_raw(tiff.into_inner()).unwrap();
+        assert_eq!(thumbnail(&exif), Some(&thumb[..]));
+    }
+
+    #[test]
+    fn takes_the_offset_into_account() {
+        let date = LocalDate {
+            local: local("2020-01-02 12:00:00"),
+            offset: Some(120),
+        };
+        assert_eq!(date.utc(), local("2020-01-02 10:00:00"));
+        assert_eq!(date.timestamp(), Some(1577959200));
+        assert_eq!(date.to_iso8601(), "2020-01-02T12:00:00+02:00");
+        let date = LocalDate {
+            offset: Some(-330),
+            ..date
+        };
+        assert_eq!(date.to_iso8601(), "2020-01-02T12:00:00-05:30");
+    }
+
+    #[test]
+    fn takes_times_without_an_offset_as_local() {
+        let date = LocalDate {
+            local: local("2020-07-01 12:00:00"),
+            offset: None,
+        };
+        let zone = Local
+            .offset_from_local_datetime(&date.local)
+            .unwrap()
+            .local_minus_utc();
+        assert_eq!(
+            date.timestamp(),
+            Some(local("2020-07-01 12:00:00").timestamp() - zone as i64)
+        );
+        assert_eq!(date.to_iso8601(), "2020-07-01T12:00:00");
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A problem with a single file. The task reports these and carries on with the next file.
+#[derive(Debug, Clone)]
+pub enum TaskError {
+    Io { path: PathBuf, message: String },
+    Parse { path: PathBuf, message: String },
+    Collision { path: PathBuf, existing: PathBuf },
+    Permission { path: PathBuf, message: String },
+}
+
+impl TaskError {
+    pub fn io(path: impl AsRef<Path>, error: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let message = error.to_string();
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Self::Permission { path, message },
+            io::ErrorKind::InvalidData => Self::Parse { path, message },
+            _ => Self::Io { path, message },
+        }
+    }
+
+    pub fn parse(path: impl AsRef<Path>, message: impl Into<String>) -> Self {
+        Self::Parse {
+            path: path.as_ref().to_path_buf(),
+            message: message.into(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. }
+            | Self::Parse { path, .. }
+            | Self::Collision { path, .. }
+            | Self::Permission { path, .. } => path,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Io { .. } => "I/O error",
+            Self::Parse { .. } => "Parse error",
+            Self::Collision { .. } => "Name collision",
+            Self::Permission { .. } => "Permission denied",
+        }
+    }
+}
+
+impl Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = crate::display_path(self.path());
+        match self {
+            Self::Io { message, .. }
+            | Self::Parse { message, .. }
+            | Self::Permission { message, .. } => {
+                write!(f, "{} ({}): {}", self.kind(), path, message)
+            }
+            Self::Collision { existing, .. } => write!(
+                f,
+                "{} ({}): already written for {}",
+                self.kind(),
+                path,
+                crate::display_path(existing)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_io_errors_by_kind() {
+        let error = |kind| TaskError::io("a.jpg", io::Error::new(kind, "nope"));
+        assert!(matches!(
+            error(io::ErrorKind::PermissionDenied),
+            TaskError::Permission { .. }
+        ));
+        assert!(matches!(
+            error(io::ErrorKind::InvalidData),
+            TaskError::Parse { .. }
+        ));
+        assert!(matches!(
+            error(io::ErrorKind::NotFound),
+            TaskError::Io { .. }
+        ));
+    }
+
+    #[test]
+    fn names_the_file_and_the_kind() {
+        let error = TaskError::parse("a.jpg", "Invalid EXIF data");
+        assert_eq!(error.to_string(), "Parse error (a.jpg): Invalid EXIF data");
+        let error = TaskError::Collision {
+            path: PathBuf::from("b.jpg"),
+            existing: PathBuf::from("a.jpg"),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Name collision (b.jpg): already written for a.jpg"
+        );
+        assert_eq!(error.path(), Path::new("b.jpg"));
+    }
+}
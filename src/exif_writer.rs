@@ -0,0 +1,263 @@
+use std::io::{self, Cursor};
+
+use chrono::NaiveDateTime;
+use exif::experimental::Writer;
+use exif::{Exif, Field, In, Tag, Value};
+
+use crate::date::thumbnail;
+use crate::jpeg;
+
+const DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn ascii(tag: Tag, value: String) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.into_bytes()]),
+    }
+}
+
+/// Gives the JPEG in `buf` a `DateTimeOriginal` of `date`, with `offset` (like `+02:00`) as its
+/// `OffsetTimeOriginal` if it's known. An EXIF segment is added if the photo has none. Returns
+/// `None` if the photo already has a date.
+///
+/// The EXIF is written anew, so maker notes that point outside of themselves may not survive.
+pub fn with_date_time_original(
+    buf: &[u8],
+    date: NaiveDateTime,
+    offset: Option<&str>,
+) -> io::Result<Option<Vec<u8>>> {
+    write_date_time_original(buf, date, offset)
+}
+
+/// Moves the `DateTime`, `DateTimeOriginal` and `DateTimeDigitized` of the JPEG in `buf` with
+/// `shift`, so they keep agreeing with each other. Returns `None` if the photo has none of them.
+pub fn with_shifted_dates(
+    buf: &[u8],
+    shift: impl Fn(NaiveDateTime) -> Option<NaiveDateTime>,
+) -> io::Result<Option<Vec<u8>>> {
+    let (segments, existing, exif) = read_exif(buf)?;
+    let exif = match exif {
+        Some(exif) => exif,
+        None => return Ok(None),
+    };
+    let mut shifted = Vec::new();
+    for tag in [Tag::DateTime, Tag::DateTimeOriginal, Tag::DateTimeDigitized] {
+        let date = match exif.get_field(tag, In::PRIMARY).map(|field| &field.value) {
+            Some(Value::Ascii(values)) => {
+                values.first().map(|value| String::from_utf8_lossy(value))
+            }
+            _ => None,
+        };
+        // Unset dates are left as they are
+        let date = match date
+            .and_then(|date| NaiveDateTime::parse_from_str(date.trim(), DATE_FORMAT).ok())
+        {
+            Some(date) => date,
+            None => continue,
+        };
+        let date = shift(date).ok_or_else(|| invalid("The shifted date is out of range"))?;
+        shifted.push(ascii(tag, date.format(DATE_FORMAT).to_string()));
+    }
+    if shifted.is_empty() {
+        return Ok(None);
+    }
+    write_fields(buf, &segments, existing, Some(&exif), &shifted).map(Some)
+}
+
+/// The segments of the JPEG in `buf`, along with its EXIF segment and what's in it.
+fn read_exif(buf: &[u8]) -> io::Result<(Vec<jpeg::Segment>, Option<jpeg::Segment>, Option<Exif>)> {
+    let segments = jpeg::segments(buf).ok_or_else(|| invalid("The photo is not a JPEG"))?;
+    let existing = segments.iter().copied().find(|segment| {
+        segment.marker == jpeg::APP1 && buf[segment.payload()].starts_with(jpeg::EXIF_HEADER)
+    });
+    let exif = match existing {
+        Some(segment) => {
+            let tiff = buf[segment.payload()][jpeg::EXIF_HEADER.len()..].to_vec();
+            let exif = exif::Reader::new()
+                .read_raw(tiff)
+                .map_err(|e| invalid(format!("Invalid EXIF data: {}", e)))?;
+            Some(exif)
+        }
+        None => None,
+    };
+    Ok((segments, existing, exif))
+}
+
+fn write_date_time_original(
+    buf: &[u8],
+    date: NaiveDateTime,
+    offset: Option<&str>,
+) -> io::Result<Option<Vec<u8>>> {
+    let (segments, existing, exif) = read_exif(buf)?;
+    let has_date = matches!(
+        &exif,
+        Some(exif) if exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_some()
+    );
+    if has_date {
+        return Ok(None);
+    }
+
+    let mut fields = vec![ascii(
+        Tag::DateTimeOriginal,
+        date.format(DATE_FORMAT).to_string(),
+    )];
+    if let Some(offset) = offset {
+        fields.push(ascii(Tag::OffsetTimeOriginal, offset.to_string()));
+    }
+    write_fields(buf, &segments, existing, exif.as_ref(), &fields).map(Some)
+}
+
+/// Writes the EXIF of `buf` anew with `fields` in place of the ones with the same tag.
+fn write_fields(
+    buf: &[u8],
+    segments: &[jpeg::Segment],
+    existing: Option<jpeg::Segment>,
+    exif: Option<&Exif>,
+    fields: &[Field],
+) -> io::Result<Vec<u8>> {
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut little_endian = false;
+    if let Some(exif) = exif {
+        little_endian = exif.little_endian();
+        for field in exif.fields() {
+            let replaced = fields
+                .iter()
+                .any(|new| new.tag == field.tag && new.ifd_num == field.ifd_num);
+            // Any further IFDs aren't part of the EXIF standard
+            if (field.ifd_num == In::PRIMARY || field.ifd_num == In::THUMBNAIL) && !replaced {
+                writer.push_field(field);
+            }
+        }
+        // The writer has to be handed the thumbnail separately
+        if let Some(thumbnail) = thumbnail(exif) {
+            writer.set_jpeg(thumbnail, In::THUMBNAIL);
+        }
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer
+        .write(&mut tiff, little_endian)
+        .map_err(|e| invalid(format!("Unable to write EXIF data: {}", e)))?;
+    let segment = jpeg::app1_segment(jpeg::EXIF_HEADER, tiff.get_ref())
+        .ok_or_else(|| invalid("The EXIF data is too large"))?;
+
+    // A new EXIF segment goes right after the JFIF header, if there is one
+    let (start, end) = match existing {
+        Some(existing) => (existing.start, existing.end),
+        None => {
+            let at = segments
+                .iter()
+                .take_while(|segment| segment.marker == 0xE0)
+                .last()
+                .map_or(2, |segment| segment.end);
+            (at, at)
+        }
+    };
+    let mut dated = Vec::with_capacity(buf.len() + segment.len());
+    dated.extend_from_slice(&buf[..start]);
+    dated.extend_from_slice(&segment);
+    dated.extend_from_slice(&buf[end..]);
+    Ok(dated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(fields: &[Field]) -> Vec<u8> {
+        let mut buf = vec![0xFF, 0xD8];
+        if !fields.is_empty() {
+            let mut writer = Writer::new();
+            for field in fields {
+                writer.push_field(field);
+            }
+            let mut tiff = Cursor::new(Vec::new());
+            writer.write(&mut tiff, false).unwrap();
+            buf.extend(jpeg::app1_segment(jpeg::EXIF_HEADER, tiff.get_ref()).unwrap());
+        }
+        buf.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9]);
+        buf
+    }
+
+    fn date_of(buf: &[u8], tag: Tag) -> Option<String> {
+        let (_, _, exif) = read_exif(buf).unwrap();
+        exif?
+            .get_field(tag, In::PRIMARY)
+            .map(|field| field.display_value().to_string())
+    }
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, DATE_FORMAT).unwrap()
+    }
+
+    #[test]
+    fn adds_a_missing_date() {
+        let buf = photo(&[]);
+        let dated = with_date_time_original(&buf, time("2020:01:02 03:04:05"), Some("+02:00"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            date_of(&dated, Tag::DateTimeOriginal).as_deref(),
+            Some("2020-01-02 03:04:05")
+        );
+        assert_eq!(
+            date_of(&dated, Tag::OffsetTimeOriginal).as_deref(),
+            Some("\"+02:00\"")
+        );
+        // The image data is left alone
+        assert!(dated.ends_with(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9]));
+    }
+
+    #[test]
+    fn keeps_an_existing_date() {
+        let buf = photo(&[ascii(Tag::DateTimeOriginal, "2019:01:01 00:00:00".into())]);
+        assert!(
+            with_date_time_original(&buf, time("2020:01:01 00:00:00"), None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn shifts_all_dates() {
+        let buf = photo(&[
+            ascii(Tag::Make, "Canon".into()),
+            ascii(Tag::DateTime, "2020:01:01 10:00:00".into()),
+            ascii(Tag::DateTimeOriginal, "2020:01:01 09:00:00".into()),
+            ascii(Tag::DateTimeDigitized, "2020:01:01 09:00:00".into()),
+        ]);
+        let shifted = with_shifted_dates(&buf, |date| Some(date + chrono::Duration::hours(1)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            date_of(&shifted, Tag::DateTime).as_deref(),
+            Some("2020-01-01 11:00:00")
+        );
+        assert_eq!(
+            date_of(&shifted, Tag::DateTimeOriginal).as_deref(),
+            Some("2020-01-01 10:00:00")
+        );
+        assert_eq!(
+            date_of(&shifted, Tag::DateTimeDigitized).as_deref(),
+            Some("2020-01-01 10:00:00")
+        );
+        assert_eq!(date_of(&shifted, Tag::Make).as_deref(), Some("\"Canon\""));
+    }
+
+    #[test]
+    fn shifting_needs_dates() {
+        assert!(with_shifted_dates(&photo(&[]), Some).unwrap().is_none());
+        let buf = photo(&[ascii(Tag::Make, "Canon".into())]);
+        assert!(with_shifted_dates(&buf, Some).unwrap().is_none());
+        let buf = photo(&[ascii(Tag::DateTime, "2020:01:01 10:00:00".into())]);
+        assert!(with_shifted_dates(&buf, |_| None).is_err());
+        assert!(with_shifted_dates(b"not a jpeg", Some).is_err());
+    }
+}
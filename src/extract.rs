@@ -1,33 +1,353 @@
+use std::fmt::Display;
 use std::fs::{read, write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-pub fn extract_mp4(path: impl AsRef<Path>) -> std::io::Result<()> {
-    let magic: &[u8; 16] = b"MotionPhoto_Data";
+use chrono::NaiveDateTime;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::jpeg;
+use crate::mp4::Metadata;
+
+/// The type Samsung uses for the video entry in its trailer.
+pub const SEF_MOTION_PHOTO_DATA: u16 = 0x0A30;
+pub const SEF_MOTION_PHOTO_NAME: &[u8] = b"MotionPhoto_Data";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum MotionFormat {
+    Samsung,
+    Google,
+    SamsungHeic,
+    GoogleHeic,
+    HeifItem,
+}
+
+impl Display for MotionFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Samsung => "Samsung",
+                Self::Google => "Google",
+                Self::SamsungHeic => "Samsung HEIC",
+                Self::GoogleHeic => "Google HEIC",
+                Self::HeifItem => "HEIF video item",
+            }
+        )
+    }
+}
+
+/// Where the video embedded in a motion photo is located.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Motion {
+    pub format: MotionFormat,
+    pub offset: usize,
+    pub length: usize,
+    /// The moment in the video the still was taken from, in microseconds
+    pub presentation_timestamp_us: Option<i64>,
+}
+
+impl Motion {
+    pub fn video<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[self.offset..self.offset + self.length]
+    }
+}
+
+impl Display for Motion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} motion photo, {} byte video",
+            self.format, self.length
+        )?;
+        match self.presentation_timestamp_us {
+            Some(timestamp) => write!(f, ", still at {:.3}s", timestamp as f64 / 1_000_000.0),
+            None => write!(f, ", still frame unknown"),
+        }
+    }
+}
+
+pub fn find_motion(buf: &[u8]) -> Option<Motion> {
+    if crate::isobmff::is_heif(buf) {
+        return crate::heif::find_motion(buf);
+    }
+
+    let mut motion = find_samsung_motion(buf).or_else(|| find_google_motion(buf))?;
+    motion.presentation_timestamp_us = presentation_timestamp(buf);
+    Some(motion)
+}
+
+/// Which frame of the video the still shows. Google writes it into the XMP, and newer Samsung
+/// phones write the same XMP next to their trailer. Newer Pixels declare the camera namespace as
+/// `Camera` rather than `GCamera`. A negative value means it's unspecified.
+fn presentation_timestamp(buf: &[u8]) -> Option<i64> {
+    let xmp = String::from_utf8_lossy(jpeg::xmp(buf)?);
+    let timestamp = Regex::new(
+        r#"\bG?Camera:(?:MotionPhoto|MicroVideo)PresentationTimestampUs(?:="|>)(-?\d+)"#,
+    )
+    .unwrap();
+    timestamp
+        .captures(&xmp)
+        .and_then(|captures| captures[1].parse().ok())
+        .filter(|timestamp| *timestamp >= 0)
+}
+
+fn find_samsung_motion(buf: &[u8]) -> Option<Motion> {
+    if let Some(video) = find_sef_entry(buf, SEF_MOTION_PHOTO_DATA) {
+        return Some(Motion {
+            format: MotionFormat::Samsung,
+            offset: video.start,
+            length: video.end - video.start,
+            presentation_timestamp_us: None,
+        });
+    }
+
+    // Without a readable trailer directory, the video runs from the marker to the end
+    let magic = SEF_MOTION_PHOTO_NAME;
+    let idx = (0..buf.len().saturating_sub(magic.len())).find(|start| {
+        let end = start + magic.len();
+        &buf[*start..end] == magic
+    })?;
+
+    let offset = idx + magic.len();
+    Some(Motion {
+        format: MotionFormat::Samsung,
+        offset,
+        length: buf.len() - offset,
+        presentation_timestamp_us: None,
+    })
+}
+
+/// Finds the data of an entry in the trailer Samsung appends to its photos. The trailer ends
+/// with a directory (`SEFH` ... `SEFT`) pointing back at the entries in front of it.
+fn find_sef_entry(buf: &[u8], kind: u16) -> Option<std::ops::Range<usize>> {
+    let u32_at = |at: usize| -> Option<usize> {
+        let bytes = buf.get(at..at + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let bytes = buf.get(at..at + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+
+    let len = buf.len();
+    if !buf.ends_with(b"SEFT") || len < 8 {
+        return None;
+    }
+    let directory = len.checked_sub(8 + u32_at(len - 8)?)?;
+    if buf.get(directory..directory + 4)? != b"SEFH" {
+        return None;
+    }
+    let count = u32_at(directory + 8)?;
+    (0..count)
+        .map(|idx| directory + 12 + idx * 12)
+        .find(|entry| u16_at(entry + 2) == Some(kind))
+        .and_then(|entry| {
+            // Entries are located relative to the directory, and start with a small header
+            let start = directory.checked_sub(u32_at(entry + 4)?)?;
+            let end = start.checked_add(u32_at(entry + 8)?)?;
+            let data = start.checked_add(8 + u32_at(start + 4)?)?;
+            if data <= end && end <= directory {
+                Some(data..end)
+            } else {
+                None
+            }
+        })
+}
+
+/// Google motion photos declare the length of the video appended to the JPEG in their XMP,
+/// either in the container directory or the older `MicroVideoOffset`.
+fn find_google_motion(buf: &[u8]) -> Option<Motion> {
+    let xmp = String::from_utf8_lossy(jpeg::xmp(buf)?);
+    let item = Regex::new(r#"<Container:Item[^>]*Item:Semantic="MotionPhoto"[^>]*>"#).unwrap();
+    let item_length = Regex::new(r#"Item:Length="(\d+)""#).unwrap();
+    let micro_video_offset = Regex::new(r#"GCamera:MicroVideoOffset(?:="|>)(\d+)"#).unwrap();
+    let length: usize = item
+        .find(&xmp)
+        .and_then(|item| item_length.captures(item.as_str()))
+        .or_else(|| micro_video_offset.captures(&xmp))
+        .and_then(|captures| captures[1].parse().ok())?;
+
+    let offset = buf.len().checked_sub(length)?;
+    if buf.get(offset + 4..offset + 8)? != b"ftyp" {
+        return None;
+    }
+    Some(Motion {
+        format: MotionFormat::Google,
+        offset,
+        length,
+        presentation_timestamp_us: None,
+    })
+}
+
+pub fn extract_mp4(path: impl AsRef<Path>, sidecar: bool) -> std::io::Result<()> {
     let path = path.as_ref();
     if path.is_dir() {
         for entry in (path.read_dir()?).flatten() {
-            extract_mp4(entry.path())?;
+            extract_mp4(entry.path(), sidecar)?;
         }
         return Ok(());
     }
 
-    let buf = read(&path)?;
-    let idx = (0..buf.len() - magic.len()).find(|start| {
-        let end = start + magic.len();
-        &buf[*start..end] == magic
-    });
+    // One broken photo shouldn't stop the rest of the directory
+    match extract_file(path, &motion_path(path), sidecar, None) {
+        Ok(Some(motion)) => println!("{}: {}", path.display(), motion),
+        Ok(None) => {}
+        Err(e) => eprintln!("{}: {}", path.display(), e),
+    }
+    Ok(())
+}
 
-    if idx.is_none() {
-        return Ok(());
+/// Writes the video embedded in the photo at `path` to `destination`, returning where it was
+/// found, or `None` if the photo has no motion data. With `sidecar`, the details of the motion
+/// data, including which frame the still shows, are written next to the video as JSON. The video
+/// is dated `date` in UTC, if given, rather than when the photo's EXIF says it was taken.
+pub fn extract_file(
+    path: &Path,
+    destination: &Path,
+    sidecar: bool,
+    date: Option<NaiveDateTime>,
+) -> std::io::Result<Option<Motion>> {
+    let buf = read(path)?;
+    let mut motion = match find_motion(&buf) {
+        Some(motion) => motion,
+        None => return Ok(None),
+    };
+    // Rather no video than a broken one, and whatever follows the video isn't part of it
+    motion.length = crate::mp4::validate(motion.video(&buf))?;
+
+    // The video gets the date and location of the still, so the two sort together
+    let video = motion.video(&buf);
+    let mut metadata = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(&buf))
+        .map(|exif| Metadata::from_exif(&exif))
+        .unwrap_or_default();
+    if date.is_some() {
+        metadata.date = date;
     }
-    let idx = idx.unwrap();
+    let video = crate::mp4::with_metadata(video, &metadata);
+    write(
+        destination,
+        video.as_deref().unwrap_or_else(|| motion.video(&buf)),
+    )?;
+    if sidecar {
+        let json = serde_json::to_string_pretty(&motion)?;
+        write(destination.with_extension("json"), json)?;
+    }
+    Ok(Some(motion))
+}
 
+/// `<stem>-motion.mp4` next to the photo at `path`.
+pub fn motion_path(path: &Path) -> PathBuf {
     let mut path_buf = path.to_path_buf();
     path_buf.set_extension("");
-    let mut file_name = path_buf.file_name().unwrap().to_owned();
+    let mut file_name = path_buf.file_name().unwrap_or_default().to_owned();
     file_name.push("-motion.mp4");
     path_buf.set_file_name(file_name);
+    path_buf
+}
 
-    write(path_buf, &buf[idx + magic.len()..])?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trailer with a single entry of `kind` holding `data`, laid out like Samsung's camera does.
+    fn sef_trailer(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut trailer = 0u16.to_le_bytes().to_vec();
+        trailer.extend_from_slice(&kind.to_le_bytes());
+        trailer.extend_from_slice(&(SEF_MOTION_PHOTO_NAME.len() as u32).to_le_bytes());
+        trailer.extend_from_slice(SEF_MOTION_PHOTO_NAME);
+        trailer.extend_from_slice(data);
+        let entry_len = trailer.len() as u32;
+        let directory = trailer.len();
+        trailer.extend_from_slice(b"SEFH");
+        trailer.extend_from_slice(&106u32.to_le_bytes());
+        trailer.extend_from_slice(&1u32.to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes());
+        trailer.extend_from_slice(&kind.to_le_bytes());
+        trailer.extend_from_slice(&entry_len.to_le_bytes());
+        trailer.extend_from_slice(&entry_len.to_le_bytes());
+        let directory_len = (trailer.len() - directory) as u32;
+        trailer.extend_from_slice(&directory_len.to_le_bytes());
+        trailer.extend_from_slice(b"SEFT");
+        trailer
+    }
+
+    fn photo(trailer: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0xFF, 0xD8, 0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9];
+        buf.extend_from_slice(trailer);
+        buf
+    }
+
+    #[test]
+    fn finds_entries_through_the_directory() {
+        let buf = photo(&sef_trailer(SEF_MOTION_PHOTO_DATA, b"video"));
+        let range = find_sef_entry(&buf, SEF_MOTION_PHOTO_DATA).unwrap();
+        assert_eq!(&buf[range], b"video");
+        assert!(find_sef_entry(&buf, 0x0001).is_none());
+
+        let motion = find_motion(&buf).unwrap();
+        assert_eq!(motion.format, MotionFormat::Samsung);
+        assert_eq!(motion.video(&buf), b"video");
+    }
+
+    #[test]
+    fn rejects_directories_pointing_outside_the_file() {
+        let mut trailer = sef_trailer(SEF_MOTION_PHOTO_DATA, b"video");
+        let len = trailer.len();
+        trailer[len - 8..len - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(find_sef_entry(&photo(&trailer), SEF_MOTION_PHOTO_DATA).is_none());
+
+        // The entry claims to start before the file does
+        let mut trailer = sef_trailer(SEF_MOTION_PHOTO_DATA, b"video");
+        let entry = trailer.len() - 8 - 24 + 12;
+        trailer[entry + 4..entry + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(find_sef_entry(&photo(&trailer), SEF_MOTION_PHOTO_DATA).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_the_marker_without_a_directory() {
+        let mut trailer = SEF_MOTION_PHOTO_NAME.to_vec();
+        trailer.extend_from_slice(b"video");
+        let buf = photo(&trailer);
+        assert!(find_sef_entry(&buf, SEF_MOTION_PHOTO_DATA).is_none());
+        assert_eq!(find_motion(&buf).unwrap().video(&buf), b"video");
+    }
+
+    #[test]
+    fn reads_the_presentation_timestamp_with_either_prefix() {
+        let with_xmp = |xmp: &str| {
+            let mut buf = vec![0xFF, 0xD8];
+            buf.extend(jpeg::app1_segment(jpeg::XMP_NAMESPACE, xmp.as_bytes()).unwrap());
+            buf.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9]);
+            buf
+        };
+        let timestamp = |xmp| presentation_timestamp(&with_xmp(xmp));
+        assert_eq!(
+            timestamp(r#"<rdf:Description GCamera:MotionPhotoPresentationTimestampUs="1500"/>"#),
+            Some(1500)
+        );
+        assert_eq!(
+            timestamp(r#"<rdf:Description Camera:MotionPhotoPresentationTimestampUs="2500"/>"#),
+            Some(2500)
+        );
+        assert_eq!(
+            timestamp("<GCamera:MicroVideoPresentationTimestampUs>300</GCamera:MicroVideoPresentationTimestampUs>"),
+            Some(300)
+        );
+        assert_eq!(
+            timestamp(r#"<rdf:Description GCamera:MotionPhotoPresentationTimestampUs="-1"/>"#),
+            None
+        );
+        assert_eq!(
+            timestamp(r#"<rdf:Description MyCamera:MotionPhotoPresentationTimestampUs="1"/>"#),
+            None
+        );
+    }
+
+    #[test]
+    fn photos_without_a_trailer_have_no_motion() {
+        assert!(find_motion(&photo(b"")).is_none());
+    }
 }
@@ -1,40 +1,113 @@
 use std::collections::{HashSet, VecDeque, HashMap};
-use std::ffi::OsStr;
-use std::fs::{read, write};
+use std::fmt::Display;
+use std::fs::{create_dir_all, read, write, File};
 use std::hash::{Hash, Hasher};
-use std::io::Cursor;
+use std::io::{BufReader, Cursor};
 use std::path::{Path, PathBuf};
 use std::thread::spawn;
 
+use chrono::{NaiveDate, NaiveDateTime};
 use exif::{Exif, Reader, Tag, In};
+use filetime::FileTime;
 use iced_futures::futures;
 use iced_futures::subscription::Recipe;
 use itertools::Itertools;
 use regex::Regex;
 use ring::digest::{Context, SHA256};
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
+use crate::error::TaskError;
+use crate::date::{DateSource, LocalDate};
+use crate::extract::{find_motion, Motion, MotionFormat};
+use crate::filter::{FileFilter, FileKind, PhotoFilter};
+use crate::shift::{TimeShift, TimeShifts};
+use crate::journal::Journal;
+use crate::naming::{NameTemplate, VideoNaming};
+use crate::progress::{Phase, Progress};
+use crate::report::Summary;
+use crate::review::{self, Candidate, Decision, Row};
+use crate::sidecar::{self, Analysis};
+
 #[derive(Debug, Clone)]
 pub enum Update {
-    Progress {
-        path: PathBuf,
-        done: u32,
-        total: u32,
+    Progress(Progress),
+    Error(TaskError),
+    /// The plan is ready, and waits for the user to look it over
+    Review {
+        rows: Vec<Row>,
+        reply: review::Reply,
+    },
+    Finished {
+        errors: Vec<TaskError>,
+        summary: Summary,
     },
-    Error(String),
+}
+
+/// The settings of a run, as picked in the UI.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct Options {
+    pub filter_duplicates: bool,
+    pub rename_files: bool,
+    pub extract_mp4: bool,
+    /// Writes which frame the still shows next to each extracted video
+    pub motion_sidecar: bool,
+    pub other_media: bool,
+    /// Dates the written files with the moment the photo was taken
+    pub set_file_times: bool,
+    pub name_template: NameTemplate,
+    pub video_naming: VideoNaming,
+    /// Puts extracted videos into this folder, relative to where their photo was written
+    pub video_folder: String,
+    /// Gives copied JPEGs without a `DateTimeOriginal` the date they were renamed with
+    pub write_exif_date: bool,
+    /// Writes an XMP with the date and a JSON with everything that was found out next to each copy
+    pub write_sidecars: bool,
+    /// Rewrites the copied motion photos into this format. Only renamed photos are copied.
+    pub convert_to: Option<MotionFormat>,
+    pub filter: FileFilter,
+    /// Leaves out the photos that don't match, before anything is written
+    pub photo_filter: PhotoFilter,
+    /// Corrects the dates of photos from cameras whose clock was off
+    pub time_shifts: TimeShifts,
+    /// Writes the shifted dates into the EXIF of copied JPEGs as well
+    pub shift_exif: bool,
+}
+
+impl Display for Options {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "filter_duplicates={} rename_files={} extract_mp4={} motion_sidecar={} other_media={} set_file_times={} name_template={} video_naming={:?} video_folder={} write_exif_date={} write_sidecars={} convert_to={:?} {} {} {} shift_exif={}",
+            self.filter_duplicates, self.rename_files, self.extract_mp4, self.motion_sidecar, self.other_media, self.set_file_times,
+            self.name_template, self.video_naming, self.video_folder, self.write_exif_date, self.write_sidecars, self.convert_to, self.filter, self.photo_filter, self.time_shifts, self.shift_exif
+        )
+    }
 }
 
 pub struct FileTask<P, U> {
-    path: P,
+    /// The files and folders to go through
+    sources: Vec<P>,
     output: U,
-    filter_duplicates: bool,
-    rename_files: bool,
-    extract_mp4: bool,
+    options: Options,
 }
 
 struct Photo {
     path: PathBuf,
+    /// Which of the inputs the file was found in
+    source: usize,
+    size: u64,
+    kind: FileKind,
     exif: Option<Exif>,
+    container_date: Option<NaiveDateTime>,
+    /// From the JSON Google Takeout puts next to the photos it exports
+    takeout_date: Option<NaiveDateTime>,
+    /// Links the still and video of an Apple Live Photo
+    content_id: Option<String>,
+    /// Only known when duplicates are filtered, and not for videos that only had their header read
+    sha256: Option<String>,
+    motion: Option<Motion>,
+    shift: Option<TimeShift>,
     accurate: bool,
 }
 
@@ -43,16 +116,61 @@ impl Photo {
         self.get_exif_date().is_some()
     }
 
+    /// Takeout strips the EXIF, but its JSON is just as reliable.
+    fn has_reliable_date(&self) -> bool {
+        self.has_valid_exif_date() || self.takeout_date.is_some()
+    }
+
     fn get_exif_date(&self) -> Option<String> {
-        self.exif.as_ref().map(|exif| {
+        self.exif.as_ref().and_then(|exif| {
             exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
                 .map(|field| field.display_value().to_string())
-        }).flatten()
+        })
+    }
+
+    fn matches(&self, filter: &PhotoFilter) -> bool {
+        let date = self.get_best_effort_date().and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok());
+        filter.accepts(date, self.exif.as_ref(), self.motion.is_some())
     }
 
     fn get_best_effort_date(&self) -> Option<String> {
+        self.resolve_date().map(|(date, _)| date)
+    }
+
+    /// The date the photo was taken, along with where it was found, corrected by its time shift.
+    fn resolve_date(&self) -> Option<(String, DateSource)> {
+        let (date, source) = self.find_date()?;
+        let shift = match &self.shift {
+            Some(shift) => shift,
+            None => return Some((date, source)),
+        };
+        let time = match source {
+            DateSource::Exif => self.exif.as_ref().and_then(LocalDate::from_exif).map(|date| date.local),
+            DateSource::Takeout => self.takeout_date,
+            DateSource::Container => self.container_date,
+            DateSource::FileName => self.file_name_time(&date),
+            DateSource::Manual => None,
+        };
+        let shifted = match time {
+            Some(time) => shift.apply(time)?.date(),
+            None => shift.apply_to_date(NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?)?,
+        };
+        Some((shifted.format("%Y-%m-%d").to_string(), source))
+    }
+
+    /// The time in a name like `20190101_123456.jpg`, if the name has one and it's on `date`.
+    fn file_name_time(&self, date: &str) -> Option<NaiveDateTime> {
+        let time_regex = Regex::new(r"(\d{8}_\d{6})").unwrap();
+        let filename = self.path.file_name()?.to_string_lossy();
+        let found = time_regex.captures(&filename)?;
+        NaiveDateTime::parse_from_str(&found[1], "%Y%m%d_%H%M%S")
+            .ok()
+            .filter(|time| time.format("%Y-%m-%d").to_string() == date)
+    }
+
+    fn find_date(&self) -> Option<(String, DateSource)> {
         self.get_exif_date()
-            .map(|date| {
+            .and_then(|date| {
                 let date_sep = ":\\-_";
                 let exif_regex = Regex::new(&format!(r"(\d{{4}})[{}]?(\d{{2}})[{}]?(\d{{2}})", date_sep, date_sep)).unwrap();
                 exif_regex.captures(&date).map(|captures| {
@@ -63,22 +181,69 @@ impl Photo {
                         .collect()
                 })
             })
-            .flatten()
+            .map(|date| (date, DateSource::Exif))
+            .or_else(|| self.takeout_date.map(|date| (date.format("%Y-%m-%d").to_string(), DateSource::Takeout)))
+            .or_else(|| self.container_date.map(|date| (date.format("%Y-%m-%d").to_string(), DateSource::Container)))
             .or_else(|| {
                 let filename_regex = Regex::new(r"(?:IMG-)?(\d{4})(\d{2})(\d{2})_.*").unwrap();
-                let x = filename_regex.captures(self.path.file_name().to_owned().unwrap().to_str().unwrap())
+                let filename = self.path.file_name()?.to_string_lossy();
+                filename_regex.captures(&filename)
                     .map(|captures| {
                         #[allow(unstable_name_collisions)]
                         captures.iter().skip(1)
                             .flat_map(|group| group.map(|g| g.as_str().to_string()))
                             .intersperse(String::from("-"))
                             .collect()
-                    });
-                dbg!(&x);
-                x
+                    })
+                    .map(|date| (date, DateSource::FileName))
             })
     }
 
+    /// The full moment the photo was taken, as far as it's known. Takeout and video containers
+    /// keep it in UTC, EXIF only if the camera recorded its offset, and a day without a time is
+    /// taken as midnight.
+    fn capture_time(&self) -> Option<LocalDate> {
+        let utc = |local| LocalDate { local, offset: Some(0) };
+        let time = self.exif.as_ref()
+            .and_then(LocalDate::from_exif)
+            .or_else(|| self.takeout_date.map(utc))
+            .or_else(|| self.container_date.map(utc));
+        if let Some(time) = time {
+            return Some(LocalDate { local: self.shifted(time.local)?, ..time });
+        }
+        let (date, _) = self.find_date()?;
+        let local = match self.file_name_time(&date) {
+            Some(time) => self.shifted(time)?,
+            None => {
+                let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?;
+                match &self.shift {
+                    Some(shift) => shift.apply_to_date(date)?,
+                    None => date,
+                }
+                .and_hms(0, 0, 0)
+            }
+        };
+        Some(LocalDate { local, offset: None })
+    }
+
+    /// `None` if the shift would move the time out of range.
+    fn shifted(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        match &self.shift {
+            Some(shift) => shift.apply(time),
+            None => Some(time),
+        }
+    }
+
+    /// The date for the XMP sidecar: the local time and offset from EXIF if there is one, else
+    /// the UTC time from Takeout or the container, or just the day.
+    fn xmp_date(&self) -> Option<String> {
+        self.exif.as_ref()
+            .and_then(LocalDate::from_exif)
+            .and_then(|date| Some(LocalDate { local: self.shifted(date.local)?, ..date }.to_iso8601()))
+            .or_else(|| self.takeout_date.or(self.container_date).and_then(|date| self.shifted(date)).map(|date| date.format("%Y-%m-%dT%H:%M:%SZ").to_string()))
+            .or_else(|| self.get_best_effort_date())
+    }
+
     fn is_accurate(&self) -> bool {
         self.accurate
     }
@@ -89,154 +254,687 @@ where
     P: AsRef<Path> + Send,
     U: AsRef<Path> + Send,
 {
-    pub fn new(
-        path: P,
-        output: U,
-        filter_duplicates: bool,
-        rename_files: bool,
-        extract_mp4: bool,
-    ) -> Self {
+    pub fn new(sources: Vec<P>, output: U, options: Options) -> Self {
         Self {
-            path,
+            sources,
             output,
-            filter_duplicates,
-            rename_files,
-            extract_mp4,
+            options,
         }
     }
 
     fn start_task(&mut self) -> UnboundedReceiver<Update> {
         let (sender, receiver): (UnboundedSender<Update>, UnboundedReceiver<Update>) =
             unbounded_channel();
-        let pathclone: PathBuf = self.path.as_ref().into();
+        let sources: Vec<PathBuf> = self.sources.iter().map(|source| source.as_ref().into()).collect();
         let outclone: PathBuf = self.output.as_ref().into();
-        let rename_files = self.rename_files;
-        let filter_duplicates = self.filter_duplicates;
-        let extract_mp4 = self.extract_mp4;
+        let options = self.options.clone();
         spawn(move || {
-            let ext: Option<&OsStr> = Some("jpg".as_ref());
-            let mut deque = VecDeque::from([pathclone.clone()]);
-            let mut files = Vec::new();
-            let mut visited = HashSet::new();
-            while let Some(path) = deque.pop_front() {
-                visited.insert(path.clone());
-                if path.is_file() {
-                    if path.extension() == ext {
-                        files.push(path);
+            // A failed send means nobody is listening for updates anymore, so there's no point in continuing
+            let _ = run_task(sources, outclone, options, &sender);
+        });
+        receiver
+    }
+}
+
+fn run_task(
+    sources: Vec<PathBuf>,
+    outclone: PathBuf,
+    options: Options,
+    sender: &UnboundedSender<Update>,
+) -> Result<(), SendError<Update>> {
+    let mut errors = Vec::new();
+    let mut summary = Summary::default();
+
+    let mut deque: VecDeque<(PathBuf, usize)> = sources.iter().cloned().zip(0..).collect();
+    let mut files = Vec::new();
+    let mut scanned = 0;
+    let mut motion_photos = 0;
+    let mut visited = HashSet::new();
+    while let Some((path, source)) = deque.pop_front() {
+        visited.insert(path.clone());
+        let relative = relative_path(&sources, source, &path);
+        if path.is_file() {
+            if !options.filter.accepts(&relative) {
+                continue;
+            }
+            let sniffed = match FileKind::sniff_file(&path) {
+                // Videos are always picked up, as they may turn out to belong to a Live Photo
+                Ok(Some(kind)) if kind.is_photo() || kind == FileKind::Video || options.other_media => {
+                    path.metadata().map(|metadata| (kind, metadata.len()))
+                }
+                Ok(_) => continue,
+                Err(e) => Err(e),
+            };
+            let (kind, size) = match sniffed {
+                Ok(sniffed) => sniffed,
+                Err(e) => {
+                    report(sender, &mut errors, TaskError::io(&path, e))?;
+                    continue;
+                }
+            };
+            scanned += 1;
+            // Only the photos with a video inside are worth hashing and planning then
+            let motion_only = options.photo_filter.motion_only;
+            if motion_only {
+                let has_motion = kind.is_photo() && match read(&path) {
+                    Ok(data) => find_motion(&data).is_some(),
+                    Err(e) => {
+                        report(sender, &mut errors, TaskError::io(&path, e))?;
+                        continue;
                     }
-                } else if path.is_dir() {
-                    if let Ok(entries) = path.read_dir() {
-                        for entry in entries.flatten() {
-                            let path = entry.path();
-                            if !visited.contains(&path) {
-                                deque.push_back(path);
+                };
+                if has_motion {
+                    motion_photos += 1;
+                    files.push((path.clone(), size, kind, source));
+                }
+            } else {
+                files.push((path.clone(), size, kind, source));
+            }
+            sender.send(Update::Progress(Progress {
+                phase: Phase::Scanning,
+                path,
+                done: scanned,
+                total: 0,
+                bytes_done: 0,
+                bytes_total: 0,
+                motion_photos: Some(motion_photos).filter(|_| motion_only),
+            }))?;
+        } else if path.is_dir() {
+            // Excluded directories are skipped as a whole, the inputs themselves are always walked
+            if path != sources[source] && options.filter.is_excluded(&relative) {
+                continue;
+            }
+            match path.read_dir() {
+                Ok(entries) => {
+                    for entry in entries {
+                        match entry {
+                            Ok(entry) => {
+                                let path = entry.path();
+                                if !visited.contains(&path) {
+                                    deque.push_back((path, source));
+                                }
                             }
+                            Err(e) => report(sender, &mut errors, TaskError::io(&path, e))?,
                         }
                     }
-                    // TODO: Ignore errors? Maybe report them nicely later
                 }
+                Err(e) => report(sender, &mut errors, TaskError::io(&path, e))?,
             }
+        }
+    }
 
-            // Let's compute a hashmap of rewritables :)
-            let mut final_files: HashMap<Vec<u8>, Vec<Photo>> = HashMap::new();
-            for (idx, file) in files.iter().enumerate() {
-                if let Ok(data) = read(file) {
-                    let key: Vec<u8> = if filter_duplicates {
-                        // At some point in the future, if we want to parallelise this, we'd use a buffered
-                        // reader?
-                        let mut ctx = Context::new(&SHA256);
-                        ctx.update(&data);
-                        ctx.finish().as_ref().to_vec()
-                    } else {
-                        idx.to_be_bytes().to_vec()
-                    };
+    summary.files_scanned = scanned;
 
-                    let mut cursor = Cursor::new(&data);
-                    let exif = Reader::new().read_from_container(&mut cursor).ok();
+    // Let's compute a hashmap of rewritables :)
+    let total = files.len() as u32;
+    let bytes_total = files.iter().map(|(_, size, _, _)| size).sum();
+    let mut bytes_done = 0;
+    let mut final_files: HashMap<Vec<u8>, Vec<Photo>> = HashMap::new();
+    for (idx, (file, size, kind, source)) in files.iter().enumerate() {
+        bytes_done += size;
+        sender.send(Update::Progress(Progress {
+            phase: Phase::Hashing,
+            path: file.clone(),
+            done: idx as u32 + 1,
+            total,
+            bytes_done,
+            bytes_total,
+            motion_photos: None,
+        }))?;
+        // Without other media, videos only matter for the Live Photo they belong to, which their
+        // movie header tells
+        let header_only = *kind == FileKind::Video && !options.other_media;
+        let data = if header_only {
+            File::open(file)
+                .and_then(|file| crate::isobmff::read_box(BufReader::new(file), b"moov"))
+                .map(Option::unwrap_or_default)
+        } else {
+            read(file)
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                report(sender, &mut errors, TaskError::io(file, e))?;
+                continue;
+            }
+        };
+        let key: Vec<u8> = if options.filter_duplicates && !header_only {
+            // At some point in the future, if we want to parallelise this, we'd use a buffered
+            // reader?
+            let mut ctx = Context::new(&SHA256);
+            ctx.update(&data);
+            ctx.finish().as_ref().to_vec()
+        } else {
+            idx.to_be_bytes().to_vec()
+        };
 
-                    final_files.entry(key).or_default().push(Photo {
-                        path: file.clone(),
-                        exif,
-                        accurate: true
-                    })
-                }
+        // Videos keep their metadata in the container rather than in EXIF
+        let exif = match kind {
+            FileKind::Video => Ok(None),
+            _ => Reader::new().read_from_container(&mut Cursor::new(&data)).map(Some),
+        };
+        let exif = match exif {
+            Ok(exif) => exif,
+            // Plenty of photos simply don't have any EXIF data, that's not worth reporting
+            Err(exif::Error::NotFound(_)) => None,
+            Err(e) => {
+                report(sender, &mut errors, TaskError::parse(file, format!("Invalid EXIF data: {}", e)))?;
+                None
             }
+        };
 
-            let len = final_files.len() as u32;
-
-            for (idx, photo) in final_files.values().enumerate() {
-                // TODO: figure out correct date using `photo`
-                let photo = match photo.iter().reduce(|first, second| {
-                            // TODO: research if second condition matters (exif data should be
-                            // valid, or at least we're not able to resolve a conflict anyway...?)
-                            if !first.has_valid_exif_date() && (second.has_valid_exif_date() || (!first.is_accurate() && second.is_accurate())) {
-                                second
-                            } else {
-                                first
-                            }
-                        }) {
-                    Some(photo) => photo,
-                    None => continue
-                };
+        let content_id = match (kind, &exif) {
+            (FileKind::Video, _) => crate::live_photo::video_identifier(&data),
+            (_, Some(exif)) => crate::live_photo::still_identifier(exif),
+            _ => None,
+        };
+        let sha256 = if options.filter_duplicates && !header_only {
+            Some(key.iter().map(|byte| format!("{:02x}", byte)).collect())
+        } else {
+            None
+        };
+        let shift = options.time_shifts.find(&path_in_source(&sources[*source], file), exif.as_ref()).cloned();
+        final_files.entry(key).or_default().push(Photo {
+            path: file.clone(),
+            source: *source,
+            sha256,
+            size: *size,
+            kind: *kind,
+            content_id,
+            exif,
+            takeout_date: crate::takeout::taken_time(file),
+            container_date: if *kind == FileKind::Video {
+                crate::mp4::creation_time(&data)
+            } else {
+                None
+            },
+            motion: if kind.is_photo() {
+                find_motion(&data)
+            } else {
+                None
+            },
+            shift,
+            accurate: true
+        })
+    }
 
-                let res = {
-                    let relative = photo.path.strip_prefix(&pathclone).unwrap();
-                    let mut newpath = outclone.join(relative);
-                    let path = if rename_files {
-                        let filename = newpath.file_name().unwrap().to_owned();
-                        // println!("{:?}", filename);
-                        if let Some(date) = photo.get_best_effort_date() {
-                            let ext = newpath.extension().unwrap().to_owned();
-                            let mut date: std::ffi::OsString = date.into();
-                            let spacer: std::ffi::OsString = "_".into();
-                            date.push(spacer);
-                            date.push(filename);
-                            newpath.set_file_name(date);
-                            newpath.set_extension(ext);
-                            write(&newpath, read(&photo.path).unwrap()).unwrap();
-                            &newpath
-                        } else {
-                            &photo.path
-                        }
-                    } else {
-                        &photo.path
-                    };
-                    if extract_mp4 {
-                        crate::extract::extract_mp4(path)
+    let len = final_files.len() as u32;
+    let mut chosen = Vec::new();
+    let mut duplicates: HashMap<&Path, Vec<&Photo>> = HashMap::new();
+    for (idx, group) in final_files.values().enumerate() {
+        // TODO: figure out correct date using `photo`
+        let photo = match group.iter().reduce(|first, second| {
+                    // TODO: research if second condition matters (exif data should be
+                    // valid, or at least we're not able to resolve a conflict anyway...?)
+                    if !first.has_reliable_date() && (second.has_reliable_date() || (!first.is_accurate() && second.is_accurate())) {
+                        second
                     } else {
-                        Ok(())
+                        first
                     }
-                };
+                }) {
+            Some(photo) => photo,
+            None => continue
+        };
+        summary.duplicates_skipped += group.len() as u32 - 1;
+        if let Some(motion) = photo.motion {
+            *summary.motion_photos.entry(motion.format).or_default() += 1;
+        }
+        let skipped = group.iter().filter(|other| other.path != photo.path);
+        duplicates.insert(photo.path.as_path(), skipped.collect());
+        sender.send(Update::Progress(Progress {
+            phase: Phase::Planning,
+            path: photo.path.clone(),
+            done: idx as u32 + 1,
+            total: len,
+            bytes_done: 0,
+            bytes_total: 0,
+            motion_photos: None,
+        }))?;
+        chosen.push(photo);
+    }
 
-                let res = match res {
-                    Err(e) => {
-                        let res = sender.send(Update::Error(e.to_string()));
-                        std::thread::sleep(std::time::Duration::from_millis(10));
-                        res
-                    }
-                    _ => sender.send(Update::Progress {
-                        path: photo.path.to_owned(),
-                        done: idx as u32,
-                        total: len,
-                    }),
-                };
-                if res.is_err() {
-                    panic!("Failed to send message");
+    // The video of an Apple Live Photo goes wherever its still goes, so it's planned along with it
+    let mut live_videos: HashMap<&str, &Photo> = chosen
+        .iter()
+        .filter(|photo| photo.kind == FileKind::Video)
+        .filter_map(|photo| Some((photo.content_id.as_deref()?, *photo)))
+        .collect();
+    let mut paired = HashSet::new();
+    let mut plan = Vec::new();
+    for photo in chosen.iter().filter(|photo| photo.kind.is_photo()) {
+        let live_video = photo.content_id.as_deref().and_then(|id| live_videos.remove(id));
+        if !options.photo_filter.is_empty() && !photo.matches(&options.photo_filter) {
+            // The video of a Live Photo that's left out is left out along with it
+            if let Some(video) = live_video {
+                paired.insert(&video.path);
+            }
+            summary.filtered_out += 1;
+            continue;
+        }
+        if let Some(video) = live_video {
+            paired.insert(&video.path);
+            summary.live_photos += 1;
+        }
+        let duplicates = duplicates.remove(photo.path.as_path()).unwrap_or_default();
+        plan.push(Planned { photo, live_video, duplicates, date: None });
+    }
+    for photo in chosen.iter().filter(|photo| !photo.kind.is_photo()) {
+        // Without other media enabled, videos were only picked up to pair them with their still
+        if paired.contains(&photo.path) || !options.other_media {
+            continue;
+        }
+        summary.other_media += 1;
+        let duplicates = duplicates.remove(photo.path.as_path()).unwrap_or_default();
+        plan.push(Planned { photo, live_video: None, duplicates, date: None });
+    }
+
+    // Nothing is written until the user has looked over the plan
+    let (reply, decisions) = std::sync::mpsc::channel();
+    let rows = plan.iter().map(|planned| planned.row(&sources)).collect();
+    sender.send(Update::Review { rows, reply })?;
+    let decisions: Vec<Decision> = match decisions.recv() {
+        Ok(Some(decisions)) => decisions,
+        // Cancelled, or the window was closed
+        _ => return Ok(()),
+    };
+    let plan: Vec<Planned> = plan
+        .into_iter()
+        .zip(decisions)
+        .filter_map(|(planned, decision)| {
+            if !decision.include {
+                summary.excluded += 1;
+                return None;
+            }
+            // The copies are identical, so another one can simply take the place of the one that was picked
+            let mut candidates = planned.duplicates.clone();
+            candidates.insert(0, planned.photo);
+            if decision.keep >= candidates.len() {
+                return Some(Planned { date: decision.date, ..planned });
+            }
+            let photo = candidates.remove(decision.keep);
+            Some(Planned { photo, duplicates: candidates, date: decision.date, ..planned })
+        })
+        .collect();
+
+    // The journal lives in the destination, so a rerun with the same settings picks up where this one stopped
+    let journal_dir = if outclone.is_dir() {
+        outclone.clone()
+    } else {
+        outclone.parent().map(Path::to_path_buf).unwrap_or_default()
+    };
+    let inputs = sources.iter().map(|source| source.display()).join(", ");
+    let settings = format!("{} -> {} {}", inputs, outclone.display(), options);
+    let mut journal = match Journal::open(&journal_dir, &settings) {
+        Ok(journal) => Some(journal),
+        Err(e) => {
+            report(sender, &mut errors, TaskError::io(&journal_dir, e))?;
+            None
+        }
+    };
+
+    let total = plan.len() as u32;
+    let bytes_total = plan.iter().map(Planned::size).sum();
+    let mut bytes_done = 0;
+    let mut written = HashMap::new();
+    // Unlike the warnings in `errors`, these files weren't written and have to be retried
+    let mut failed = 0;
+    for (idx, planned) in plan.iter().enumerate() {
+        let photo = planned.photo;
+        if matches!(&journal, Some(journal) if journal.is_completed(&photo.path)) {
+            summary.resumed += 1;
+        } else {
+            let res = process_photo(planned, &sources, &outclone, &options, &mut written)
+                .and_then(|outcome| match journal.as_mut() {
+                    Some(journal) => journal.complete(&photo.path).map(|_| outcome).map_err(|e| TaskError::io(&journal_dir, e)),
+                    None => Ok(outcome),
+                });
+            match res {
+                Ok(outcome) => {
+                    summary.renamed += outcome.renamed;
+                    summary.converted += outcome.converted as u32;
+                    summary.not_converted += outcome.not_converted as u32;
+                    summary.exif_dates_written += outcome.dated as u32;
+                    summary.dates_shifted += outcome.shifted as u32;
+                    summary.videos_extracted += outcome.extracted as u32;
+                }
+                Err(e) => {
+                    failed += 1;
+                    report(sender, &mut errors, e)?;
                 }
             }
-            let res = sender.send(Update::Progress {
-                path: pathclone,
-                done: len,
-                total: len,
-            });
-            if res.is_err() {
-                panic!("Failed to send message");
+        }
+
+        bytes_done += planned.size();
+        sender.send(Update::Progress(Progress {
+            phase: Phase::Writing,
+            path: photo.path.to_owned(),
+            done: idx as u32 + 1,
+            total,
+            bytes_done,
+            bytes_total,
+            motion_photos: None,
+        }))?;
+    }
+    // Only forget about the progress if we got through everything, so the failed files are retried on a rerun
+    if failed == 0 {
+        if let Some(Err(e)) = journal.map(Journal::finish) {
+            report(sender, &mut errors, TaskError::io(&journal_dir, e))?;
+        }
+    }
+    summary.errors = errors.len() as u32;
+    // The report can't mention that it couldn't be written, so only the window does
+    if let Err(e) = summary.write(&journal_dir) {
+        report(sender, &mut errors, TaskError::io(&journal_dir, e))?;
+        summary.errors += 1;
+    }
+    sender.send(Update::Finished { errors, summary })
+}
+
+/// The path of `path` relative to the input it was found in, or just its file name if the input
+/// is that file. With several inputs, each gets a folder of its own name in the destination, so
+/// that name comes first. Inputs sharing a name, like the `DCIM` of two phones, are numbered
+/// after the first, as in `DCIM (2)`.
+fn relative_path(sources: &[PathBuf], source: usize, path: &Path) -> PathBuf {
+    let root = &sources[source];
+    let relative = path_in_source(root, path);
+    let name = match root.file_name() {
+        Some(name) if sources.len() > 1 && path != root => name,
+        _ => return relative,
+    };
+    let before = sources[..source].iter().filter(|other| other.file_name() == Some(name)).count();
+    if before == 0 {
+        return Path::new(name).join(relative);
+    }
+    let mut numbered = name.to_owned();
+    numbered.push(format!(" ({})", before + 1));
+    Path::new(&numbered).join(relative)
+}
+
+/// The path of `path` relative to `root`, or just its file name if `root` is that file.
+fn path_in_source(root: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.to_path_buf(),
+        _ => path.file_name().map_or_else(|| path.to_path_buf(), PathBuf::from),
+    }
+}
+
+fn report(
+    sender: &UnboundedSender<Update>,
+    errors: &mut Vec<TaskError>,
+    error: TaskError,
+) -> Result<(), SendError<Update>> {
+    errors.push(error.clone());
+    sender.send(Update::Error(error))
+}
+
+/// A photo that will be written out, along with the video of its Live Photo if it has one.
+struct Planned<'a> {
+    photo: &'a Photo,
+    live_video: Option<&'a Photo>,
+    /// The copies of the photo that are skipped in its favour
+    duplicates: Vec<&'a Photo>,
+    /// Entered during the review, in place of the date that was found
+    date: Option<NaiveDate>,
+}
+
+impl<'a> Planned<'a> {
+    fn size(&self) -> u64 {
+        self.photo.size + self.live_video.map_or(0, |video| video.size)
+    }
+
+    fn row(&self, sources: &[PathBuf]) -> Row {
+        let candidates = std::iter::once(self.photo).chain(self.duplicates.iter().copied()).map(|photo| {
+            let resolved = photo.resolve_date();
+            let modified = photo.path.metadata().ok()
+                .map(|metadata| FileTime::from_last_modification_time(&metadata).unix_seconds())
+                .and_then(|seconds| NaiveDateTime::from_timestamp_opt(seconds, 0))
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+            Candidate {
+                source: relative_path(sources, photo.source, &photo.path),
+                size: photo.size,
+                date: resolved.as_ref().map(|(date, _)| date.clone()),
+                date_source: resolved.map(|(_, source)| source),
+                modified,
             }
         });
-        receiver
+        Row {
+            candidates: candidates.collect(),
+            motion: self.photo.motion.map(|motion| motion.format),
+            live_video: self.live_video.is_some(),
+        }
+    }
+
+    fn resolve_date(&self) -> Option<(String, DateSource)> {
+        match self.date {
+            Some(date) => Some((date.format("%Y-%m-%d").to_string(), DateSource::Manual)),
+            None => self.photo.resolve_date(),
+        }
+    }
+
+    fn get_best_effort_date(&self) -> Option<String> {
+        self.resolve_date().map(|(date, _)| date)
+    }
+
+    /// A date entered by hand keeps the time of day that was found, if any.
+    fn capture_time(&self) -> Option<LocalDate> {
+        let time = self.photo.capture_time();
+        match self.date {
+            Some(date) => Some(match time {
+                Some(time) => LocalDate { local: date.and_time(time.local.time()), ..time },
+                None => LocalDate { local: date.and_hms(0, 0, 0), offset: None },
+            }),
+            None => time,
+        }
+    }
+
+    fn xmp_date(&self) -> Option<String> {
+        match self.date {
+            Some(date) => Some(date.format("%Y-%m-%d").to_string()),
+            None => self.photo.xmp_date(),
+        }
+    }
+}
+
+/// What happened to a single photo while writing it out.
+#[derive(Default)]
+struct Outcome {
+    renamed: u32,
+    converted: bool,
+    /// Should have been converted, but had no date to be copied under
+    not_converted: bool,
+    dated: bool,
+    shifted: bool,
+    extracted: bool,
+}
+
+fn process_photo(
+    planned: &Planned,
+    sources: &[PathBuf],
+    output: &Path,
+    options: &Options,
+    written: &mut HashMap<PathBuf, PathBuf>,
+) -> Result<Outcome, TaskError> {
+    let photo = planned.photo;
+    let mut outcome = Outcome::default();
+    let file_time = planned.capture_time()
+        .filter(|_| options.set_file_times)
+        .and_then(LocalDate::timestamp)
+        .map(|seconds| FileTime::from_unix_time(seconds, 0));
+    let mut newpath = output.join(relative_path(sources, photo.source, &photo.path));
+    let mut copied = false;
+    let path = if options.rename_files {
+        if let Some(date) = planned.get_best_effort_date() {
+            let filename = options.name_template.rename(&date, &newpath)
+                .ok_or_else(|| TaskError::parse(&photo.path, "File has no name"))?;
+            newpath.set_file_name(filename);
+            copy_to(&photo.path, &newpath, written)?;
+            copied = true;
+            outcome.renamed += 1;
+            outcome.shifted = photo.shift.is_some();
+            if needs_conversion(photo, options) {
+                if let Some(target) = options.convert_to {
+                    crate::combine::convert_file(&newpath, target)
+                        .map_err(|e| TaskError::parse(&photo.path, format!("Unable to convert the motion photo: {}", e)))?;
+                    outcome.converted = true;
+                }
+            }
+            if options.write_exif_date && photo.kind == FileKind::Jpeg && !photo.has_valid_exif_date() {
+                if let Some((time, (_, source))) = planned.capture_time().zip(planned.resolve_date()) {
+                    // Takeout and video containers keep their dates in UTC
+                    let offset = match source {
+                        DateSource::Takeout | DateSource::Container => Some("+00:00"),
+                        DateSource::Exif | DateSource::FileName | DateSource::Manual => None,
+                    };
+                    outcome.dated = write_exif_date(&newpath, time.local, offset)?;
+                }
+            } else if options.shift_exif && photo.kind == FileKind::Jpeg {
+                // The offset stays as it was, as it's the clock that was off rather than the zone
+                if let Some(shift) = &photo.shift {
+                    outcome.dated = shift_exif_dates(&newpath, shift)?;
+                }
+            }
+            set_file_time(&newpath, file_time)?;
+            // The video keeps the name of its still, so they still belong together
+            if let Some(video) = planned.live_video {
+                let ext = video.path.extension().unwrap_or_default();
+                let video_path = newpath.with_extension(ext);
+                copy_to(&video.path, &video_path, written)?;
+                set_file_time(&video_path, file_time)?;
+                outcome.renamed += 1;
+            }
+            &newpath
+        } else {
+            // The original is never rewritten
+            outcome.not_converted = needs_conversion(photo, options);
+            &photo.path
+        }
+    } else {
+        &photo.path
+    };
+    if options.extract_mp4 && photo.motion.is_some() {
+        let destination = video_destination(planned, path, options);
+        if let Some(dir) = destination.parent() {
+            create_dir_all(dir).map_err(|e| TaskError::io(dir, e))?;
+        }
+        claim(&photo.path, &destination, written)?;
+        // The EXIF of the photo isn't shifted unless asked to, so the video is dated from the plan
+        let date = planned.capture_time().map(LocalDate::utc);
+        outcome.extracted = crate::extract::extract_file(path, &destination, options.motion_sidecar, date)
+            .map_err(|e| TaskError::io(path, e))?
+            .is_some();
+        if outcome.extracted {
+            set_file_time(&destination, file_time)?;
+        }
+    }
+    // Nothing is written next to the originals, where Takeout keeps sidecars of its own
+    if options.write_sidecars && copied {
+        write_sidecars(planned, path).map_err(|e| TaskError::io(path, e))?;
+    }
+    Ok(outcome)
+}
+
+/// Only the JPEG layouts can be converted into each other.
+fn needs_conversion(photo: &Photo, options: &Options) -> bool {
+    match (options.convert_to, photo.motion) {
+        (Some(target), Some(motion)) => motion.format != target && photo.kind == FileKind::Jpeg,
+        _ => false,
     }
 }
 
+fn write_sidecars(planned: &Planned, path: &Path) -> std::io::Result<()> {
+    let photo = planned.photo;
+    let resolved = planned.resolve_date();
+    let duplicates: Vec<&Path> = planned.duplicates.iter().map(|duplicate| duplicate.path.as_path()).collect();
+    let analysis = Analysis {
+        source: &photo.path,
+        date: resolved.as_ref().map(|(date, _)| date.clone()),
+        date_source: resolved.map(|(_, source)| source),
+        capture_time: planned.xmp_date(),
+        sha256: photo.sha256.clone(),
+        duplicates: &duplicates,
+        motion: photo.motion,
+        live_video: planned.live_video.map(|video| video.path.as_path()),
+    };
+    sidecar::write_json(path, &analysis)?;
+    match planned.xmp_date() {
+        Some(date) => sidecar::write_xmp(path, &date),
+        None => Ok(()),
+    }
+}
+
+/// Where the video extracted from the planned photo goes, now that the photo itself was written
+/// to `path`.
+fn video_destination(planned: &Planned, path: &Path, options: &Options) -> PathBuf {
+    let dir = path.parent().unwrap_or_else(|| Path::new("")).join(crate::naming::video_folder(&options.video_folder));
+    let motion_name = |path: &Path| {
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+        name.push("-motion");
+        name
+    };
+    let mut name = match options.video_naming {
+        VideoNaming::Motion => motion_name(path),
+        VideoNaming::MatchPhoto => path.file_stem().unwrap_or_default().to_owned(),
+        VideoNaming::Template => match (planned.get_best_effort_date(), planned.photo.path.file_stem()) {
+            (Some(date), Some(stem)) => options.name_template.render(&date, stem),
+            _ => motion_name(path),
+        },
+    };
+    name.push(".mp4");
+    dir.join(name)
+}
+
+fn write_exif_date(path: &Path, date: NaiveDateTime, offset: Option<&str>) -> Result<bool, TaskError> {
+    let buf = read(path).map_err(|e| TaskError::io(path, e))?;
+    match crate::exif_writer::with_date_time_original(&buf, date, offset).map_err(|e| TaskError::io(path, e))? {
+        Some(dated) => write(path, dated).map(|_| true).map_err(|e| TaskError::io(path, e)),
+        None => Ok(false),
+    }
+}
+
+fn shift_exif_dates(path: &Path, shift: &TimeShift) -> Result<bool, TaskError> {
+    let buf = read(path).map_err(|e| TaskError::io(path, e))?;
+    match crate::exif_writer::with_shifted_dates(&buf, |date| shift.apply(date)).map_err(|e| TaskError::io(path, e))? {
+        Some(shifted) => write(path, shifted).map(|_| true).map_err(|e| TaskError::io(path, e)),
+        None => Ok(false),
+    }
+}
+
+fn set_file_time(path: &Path, time: Option<FileTime>) -> Result<(), TaskError> {
+    match time {
+        Some(time) => filetime::set_file_times(path, time, time).map_err(|e| TaskError::io(path, e)),
+        None => Ok(()),
+    }
+}
+
+fn copy_to(
+    source: &Path,
+    destination: &Path,
+    written: &mut HashMap<PathBuf, PathBuf>,
+) -> Result<(), TaskError> {
+    claim(source, destination, written)?;
+    if let Some(dir) = destination.parent() {
+        create_dir_all(dir).map_err(|e| TaskError::io(dir, e))?;
+    }
+    let data = read(source).map_err(|e| TaskError::io(source, e))?;
+    write(destination, data).map_err(|e| TaskError::io(destination, e))
+}
+
+/// Reserves `destination` for `source`, unless another file was already written there.
+fn claim(
+    source: &Path,
+    destination: &Path,
+    written: &mut HashMap<PathBuf, PathBuf>,
+) -> Result<(), TaskError> {
+    if let Some(existing) = written.get(destination) {
+        return Err(TaskError::Collision {
+            path: source.to_path_buf(),
+            existing: existing.clone(),
+        });
+    }
+    written.insert(destination.to_path_buf(), source.to_path_buf());
+    Ok(())
+}
+
 impl<H, I, P, U> Recipe<H, I> for FileTask<P, U>
 where
     H: Hasher,
@@ -246,11 +944,9 @@ where
     type Output = crate::Message;
 
     fn hash(&self, state: &mut H) {
-        self.path.hash(state);
+        self.sources.hash(state);
         self.output.hash(state);
-        self.filter_duplicates.hash(state);
-        self.rename_files.hash(state);
-        self.extract_mp4.hash(state);
+        self.options.hash(state);
     }
 
     fn stream(
@@ -266,3 +962,362 @@ where
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use super::*;
+    use crate::isobmff::bmff;
+
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xD9";
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("motionsplit-task-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs the task, answering its review with `decide`, and collects every update it sends.
+    fn run_reviewed(
+        sources: Vec<PathBuf>,
+        output: &Path,
+        options: Options,
+        decide: impl FnOnce(&[Row]) -> Option<Vec<Decision>>,
+    ) -> Vec<Update> {
+        let (sender, mut receiver) = unbounded_channel();
+        let output = output.to_path_buf();
+        spawn(move || run_task(sources, output, options, &sender));
+        let mut decide = Some(decide);
+        let mut updates = Vec::new();
+        while let Some(update) = receiver.blocking_recv() {
+            if let Update::Review { rows, reply } = &update {
+                let decide = decide.take().expect("The plan is only reviewed once");
+                reply.send(decide(rows)).unwrap();
+            }
+            updates.push(update);
+        }
+        updates
+    }
+
+    /// Runs the task with every planned file included, as if the review was confirmed straight away.
+    fn run(sources: Vec<PathBuf>, output: &Path, options: Options) -> (Vec<TaskError>, Summary) {
+        let include_all = |rows: &[Row]| Some(rows.iter().map(|_| Decision { include: true, keep: 0, date: None }).collect());
+        match run_reviewed(sources, output, options, include_all).pop() {
+            Some(Update::Finished { errors, summary }) => (errors, summary),
+            _ => panic!("The task stopped without finishing"),
+        }
+    }
+
+    #[test]
+    fn dates_files_by_their_name() {
+        let dir = dir("file-name");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_123456.jpg"), JPEG).unwrap();
+
+        let options = Options { rename_files: true, ..Options::default() };
+        let (errors, summary) = run(vec![input], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(summary.renamed, 1);
+        assert!(output.join("2020-01-02_20200102_123456.jpg").is_file());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_other_media_only_when_asked() {
+        let dir = dir("other-media");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_still.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        // Created 2021-06-01 12:00:00 UTC, in seconds since 1904
+        let mut mvhd = vec![0; 4];
+        mvhd.extend_from_slice(&3705393600u32.to_be_bytes());
+        let mut video = bmff(b"ftyp", b"isom\0\0\0\0mp42");
+        video.extend(bmff(b"moov", &bmff(b"mvhd", &mvhd)));
+        write(input.join("clip.mp4"), video).unwrap();
+
+        let options = Options { rename_files: true, ..Options::default() };
+        let (errors, summary) = run(vec![input.clone()], &output, options.clone());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!((summary.other_media, summary.renamed), (0, 0));
+
+        let options = Options { other_media: true, ..options };
+        let (errors, summary) = run(vec![input], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!((summary.other_media, summary.renamed), (2, 2));
+        assert!(output.join("2020-01-02_20200102_still.png").is_file());
+        assert!(output.join("2021-06-01_clip.mp4").is_file());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pairs_live_photos() {
+        use crate::live_photo::tests::{maker_note, movie, IDENTIFIER};
+
+        let dir = dir("live-photo");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        let mut writer = exif::experimental::Writer::new();
+        let note = exif::Field {
+            tag: Tag::MakerNote,
+            ifd_num: In::PRIMARY,
+            value: exif::Value::Undefined(maker_note(IDENTIFIER), 0),
+        };
+        writer.push_field(&note);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let mut still = b"\xFF\xD8".to_vec();
+        still.extend(crate::jpeg::app1_segment(crate::jpeg::EXIF_HEADER, tiff.get_ref()).unwrap());
+        still.extend_from_slice(b"\xFF\xD9");
+        write(input.join("20200102_IMG_1.jpg"), still).unwrap();
+        let mut video = movie(IDENTIFIER, false);
+        video.extend(bmff(b"mdat", &[0; 64]));
+        write(input.join("IMG_1.mov"), video).unwrap();
+
+        let options = Options { rename_files: true, filter_duplicates: true, ..Options::default() };
+        let (errors, summary) = run(vec![input], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!((summary.live_photos, summary.renamed, summary.other_media), (1, 2, 0));
+        assert!(output.join("2020-01-02_20200102_IMG_1.jpg").is_file());
+        assert_eq!(read(output.join("2020-01-02_20200102_IMG_1.mov")).unwrap().len(), movie(IDENTIFIER, false).len() + 72);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_sidecars_next_to_copies_only() {
+        let dir = dir("sidecars");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_IMG_1.jpg"), JPEG).unwrap();
+        write(input.join("IMG_2.jpg"), JPEG).unwrap();
+
+        let options = Options { rename_files: true, write_sidecars: true, ..Options::default() };
+        let (errors, _) = run(vec![input.clone()], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(output.join("2020-01-02_20200102_IMG_1.jpg.xmp").is_file());
+        assert!(output.join("2020-01-02_20200102_IMG_1.jpg.motionsplit.json").is_file());
+        // Without a date the photo isn't copied, and nothing is written next to the original
+        let mut originals: Vec<_> = input.read_dir().unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        originals.sort();
+        assert_eq!(originals, ["20200102_IMG_1.jpg", "IMG_2.jpg"]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_out_everything_but_motion_photos() {
+        let dir = dir("motion-only");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        let mut video = bmff(b"ftyp", b"isom\0\0\0\0");
+        video.extend(bmff(b"moov", &bmff(b"mvhd", &[0; 8])));
+        video.extend(bmff(b"mdat", b"data"));
+        let still = b"\xFF\xD8\xFF\xDA\x00\x02\x01\xFF\xD9";
+        let motion = crate::combine::combine(still, &video, MotionFormat::Google, None).unwrap();
+        write(input.join("20200102_motion.jpg"), motion).unwrap();
+        write(input.join("20200103_still.jpg"), JPEG).unwrap();
+        write(input.join("20200104_other.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        write(input.join("clip.mp4"), video).unwrap();
+
+        let options = Options {
+            rename_files: true,
+            other_media: true,
+            photo_filter: PhotoFilter { motion_only: true, ..PhotoFilter::default() },
+            ..Options::default()
+        };
+        let include_all = |rows: &[Row]| Some(rows.iter().map(|_| Decision { include: true, keep: 0, date: None }).collect());
+        let updates = run_reviewed(vec![input], &output, options, include_all);
+        let mut counted = updates.iter().filter_map(|update| match update {
+            Update::Progress(progress) if progress.phase == Phase::Scanning => Some((progress.done, progress.motion_photos)),
+            _ => None,
+        });
+        assert_eq!(counted.next_back(), Some((4, Some(1))));
+        let summary = match updates.last() {
+            Some(Update::Finished { errors, summary }) if errors.is_empty() => summary,
+            _ => panic!("The task didn't finish cleanly"),
+        };
+        assert_eq!((summary.files_scanned, summary.motion_photo_count(), summary.renamed), (4, 1, 1));
+        assert_eq!(summary.other_media, 0);
+        assert!(output.join("2020-01-02_20200102_motion.jpg").is_file());
+        assert!(!output.join("2020-01-03_20200103_still.jpg").exists());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shifts_folders_within_each_input() {
+        let dir = dir("shift-folders");
+        let (first, second, output) = (dir.join("a"), dir.join("b"), dir.join("out"));
+        create_dir_all(first.join("trips")).unwrap();
+        create_dir_all(second.join("trips")).unwrap();
+        create_dir_all(&output).unwrap();
+        let mut video = bmff(b"ftyp", b"isom\0\0\0\0");
+        video.extend(bmff(b"moov", &bmff(b"mvhd", &[0; 12])));
+        video.extend(bmff(b"mdat", b"data"));
+        let still = b"\xFF\xD8\xFF\xDA\x00\x02\x01\xFF\xD9";
+        let motion = crate::combine::combine(still, &video, MotionFormat::Google, None).unwrap();
+        write(first.join("trips/20200102_motion.jpg"), motion).unwrap();
+        write(second.join("trips/20200102_still.jpg"), JPEG).unwrap();
+        // Never copied without a date, so not counted as shifted either
+        write(second.join("trips/undated.jpg"), JPEG).unwrap();
+
+        let options = Options {
+            rename_files: true,
+            extract_mp4: true,
+            time_shifts: TimeShifts::parse("folder trips: +1d").unwrap(),
+            ..Options::default()
+        };
+        let (errors, summary) = run(vec![first, second], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!((summary.renamed, summary.dates_shifted), (2, 2));
+        assert!(output.join("a/trips/2020-01-03_20200102_motion.jpg").is_file());
+        assert!(output.join("b/trips/2020-01-03_20200102_still.jpg").is_file());
+        let extracted = read(output.join("a/trips/2020-01-03_20200102_motion-motion.mp4")).unwrap();
+        assert_eq!(crate::mp4::creation_time(&extracted), Some(NaiveDate::from_ymd(2020, 1, 3).and_hms(0, 0, 0)));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn applies_the_review() {
+        let dir = dir("review");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_left_out.jpg"), JPEG).unwrap();
+        write(input.join("20200103_kept.jpg"), JPEG).unwrap();
+        write(input.join("undated.jpg"), JPEG).unwrap();
+
+        let decide = |rows: &[Row]| {
+            let decisions = rows.iter().map(|row| {
+                let source = row.candidates[0].source.to_string_lossy();
+                Decision {
+                    include: source != "20200102_left_out.jpg",
+                    keep: 0,
+                    date: Some(NaiveDate::from_ymd(2021, 5, 6)).filter(|_| source == "undated.jpg"),
+                }
+            });
+            Some(decisions.collect())
+        };
+        let options = Options { rename_files: true, ..Options::default() };
+        let summary = match run_reviewed(vec![input], &output, options, decide).pop() {
+            Some(Update::Finished { errors, summary }) if errors.is_empty() => summary,
+            _ => panic!("The task didn't finish cleanly"),
+        };
+        assert_eq!((summary.excluded, summary.renamed), (1, 2));
+        let mut written: Vec<_> = output.read_dir().unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some("jpg".as_ref()))
+            .collect();
+        written.sort();
+        assert_eq!(written, [output.join("2020-01-03_20200103_kept.jpg"), output.join("2021-05-06_undated.jpg")]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_duplicate_that_was_picked() {
+        let dir = dir("duplicates");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_copy.jpg"), JPEG).unwrap();
+        write(input.join("20200105_copy.jpg"), JPEG).unwrap();
+
+        let keep_second = |rows: &[Row]| Some(rows.iter().map(|_| Decision { include: true, keep: 1, date: None }).collect());
+        let options = Options { rename_files: true, filter_duplicates: true, ..Options::default() };
+        let updates = run_reviewed(vec![input], &output, options, keep_second);
+        let rows = match updates.iter().find(|update| matches!(update, Update::Review { .. })) {
+            Some(Update::Review { rows, .. }) => rows,
+            _ => panic!("The plan wasn't reviewed"),
+        };
+        assert_eq!(rows.len(), 1);
+        let candidates = &rows[0].candidates;
+        let mut sources: Vec<_> = candidates.iter().map(|candidate| candidate.source.to_string_lossy().into_owned()).collect();
+        sources.sort();
+        assert_eq!(sources, ["20200102_copy.jpg", "20200105_copy.jpg"]);
+        assert!(candidates.iter().all(|candidate| candidate.size == JPEG.len() as u64 && candidate.date_source == Some(DateSource::FileName)));
+
+        let kept = &candidates[1];
+        let name = format!("{}_{}", kept.date.as_ref().unwrap(), kept.source.display());
+        assert!(output.join(name).is_file());
+        let written = output.read_dir().unwrap().filter(|entry| entry.as_ref().unwrap().path().extension() == Some("jpg".as_ref()));
+        assert_eq!(written.count(), 1);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_nothing_when_the_review_is_cancelled() {
+        let dir = dir("review-cancelled");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_IMG_1.jpg"), JPEG).unwrap();
+
+        let options = Options { rename_files: true, ..Options::default() };
+        let updates = run_reviewed(vec![input], &output, options, |_| None);
+        assert!(!updates.iter().any(|update| matches!(update, Update::Finished { .. })));
+        assert_eq!(output.read_dir().unwrap().count(), 0);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gives_inputs_of_the_same_name_folders_of_their_own() {
+        let dir = dir("same-names");
+        let (first, second, output) = (dir.join("a/DCIM"), dir.join("b/DCIM"), dir.join("out"));
+        create_dir_all(first.join("Camera")).unwrap();
+        create_dir_all(&second).unwrap();
+        create_dir_all(&output).unwrap();
+        write(first.join("Camera/20200102_IMG_1.jpg"), JPEG).unwrap();
+        write(second.join("20200102_IMG_1.jpg"), JPEG).unwrap();
+
+        let sources = vec![first.clone(), second.clone()];
+        assert_eq!(relative_path(&sources, 1, &second.join("IMG_2.jpg")), Path::new("DCIM (2)/IMG_2.jpg"));
+        let options = Options { rename_files: true, ..Options::default() };
+        let (errors, summary) = run(sources, &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(summary.renamed, 2);
+        assert!(output.join("DCIM/Camera/2020-01-02_20200102_IMG_1.jpg").is_file());
+        assert!(output.join("DCIM (2)/2020-01-02_20200102_IMG_1.jpg").is_file());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_a_single_file_into_the_destination() {
+        let dir = dir("single-file");
+        let output = dir.join("out");
+        create_dir_all(&output).unwrap();
+        let photo = dir.join("20200102_IMG_1.jpg");
+        write(&photo, JPEG).unwrap();
+
+        let options = Options { rename_files: true, ..Options::default() };
+        let (errors, summary) = run(vec![photo], &output, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(summary.renamed, 1);
+        assert!(output.join("2020-01-02_20200102_IMG_1.jpg").is_file());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_going_past_invalid_exif() {
+        let dir = dir("invalid-exif");
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        create_dir_all(&input).unwrap();
+        create_dir_all(&output).unwrap();
+        write(input.join("20200102_broken.jpg"), b"\xFF\xD8\xFF\xE1\x00\x0CExif\x00\x00XXXX\xFF\xD9").unwrap();
+        write(input.join("20200103_fine.jpg"), JPEG).unwrap();
+
+        let options = Options { rename_files: true, ..Options::default() };
+        let (errors, summary) = run(vec![input.clone()], &output, options);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], TaskError::Parse { path, .. } if path == &input.join("20200102_broken.jpg")));
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.renamed, 2);
+        assert!(output.join("2020-01-02_20200102_broken.jpg").is_file());
+        assert!(output.join("2020-01-03_20200103_fine.jpg").is_file());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,522 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use exif::{Exif, In, Tag};
+use regex::Regex;
+
+/// The kind of file, as told by its first bytes rather than its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    Jpeg,
+    Heif,
+    Png,
+    Video,
+}
+
+impl FileKind {
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        const VIDEO_BRANDS: [&[u8; 4]; 15] = [
+            b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ",
+            b"qt  ", b"3gp4", b"3gp5", b"3g2a", b"mmp4", b"MSNV",
+        ];
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if crate::isobmff::is_heif(head) {
+            Some(Self::Heif)
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if crate::isobmff::has_brand(head, &VIDEO_BRANDS) || is_quicktime(head) {
+            Some(Self::Video)
+        } else {
+            None
+        }
+    }
+
+    /// Photos are what MotionSplit was made for, the other kinds are only handled on request.
+    pub fn is_photo(&self) -> bool {
+        matches!(self, Self::Jpeg | Self::Heif)
+    }
+
+    pub fn sniff_file(path: &Path) -> io::Result<Option<Self>> {
+        let mut head = Vec::with_capacity(256);
+        File::open(path)?.take(256).read_to_end(&mut head)?;
+        Ok(Self::sniff(&head))
+    }
+}
+
+/// Old QuickTime movies don't start with a `ftyp` box, but straight away with their atoms.
+fn is_quicktime(head: &[u8]) -> bool {
+    let atom = match head.get(4..8) {
+        Some(atom) => atom,
+        None => return false,
+    };
+    [b"moov", b"mdat", b"wide", b"free", b"skip"]
+        .iter()
+        .any(|kind| &kind[..] == atom)
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Extension(String),
+    Glob { regex: Regex, full_path: bool },
+}
+
+/// Either a bare extension like `jpg` or `.jpeg`, or a glob like `IMG_*` or `**/thumbnails/**`.
+/// Globs without a `/` are matched against the file name, others against the path relative to
+/// the input. Matching ignores case.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    matcher: Matcher,
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Self {
+        let source = source.trim().to_string();
+        let matcher = if source.contains(['*', '?', '/']) && !is_extension_glob(&source) {
+            let mut regex = String::from("(?i)^");
+            let mut chars = source.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    // `**` spans directories, and may also match none at all
+                    '*' if chars.peek() == Some(&'*') => {
+                        chars.next();
+                        if chars.peek() == Some(&'/') {
+                            chars.next();
+                            regex.push_str("(?:.*/)?");
+                        } else if chars.peek().is_none() && regex.ends_with('/') {
+                            regex.pop();
+                            regex.push_str("(?:/.*)?");
+                        } else {
+                            regex.push_str(".*");
+                        }
+                    }
+                    '*' => regex.push_str("[^/]*"),
+                    '?' => regex.push_str("[^/]"),
+                    c => regex.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            regex.push('$');
+            Matcher::Glob {
+                regex: Regex::new(&regex).expect("escaped glob is a valid regex"),
+                full_path: source.contains('/'),
+            }
+        } else {
+            Matcher::Extension(
+                source
+                    .trim_start_matches('*')
+                    .trim_start_matches('.')
+                    .to_lowercase(),
+            )
+        };
+        Self { source, matcher }
+    }
+
+    /// Whether `relative`, a path relative to the input, matches this pattern.
+    pub fn matches(&self, relative: &Path) -> bool {
+        match &self.matcher {
+            Matcher::Extension(ext) => {
+                let found = relative
+                    .extension()
+                    .map(|found| found.to_string_lossy().to_lowercase());
+                found.as_ref() == Some(ext)
+            }
+            Matcher::Glob { regex, full_path } => {
+                let subject = if *full_path {
+                    relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                } else {
+                    relative
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                };
+                regex.is_match(&subject)
+            }
+        }
+    }
+}
+
+/// `*.jpg` is written like a glob, but means the same as the extension `jpg`.
+fn is_extension_glob(source: &str) -> bool {
+    matches!(source.strip_prefix("*."), Some(ext) if !ext.contains(['*', '?', '/', '.']))
+}
+
+/// The user's include and exclude lists. An empty include list lets every file through, after
+/// which the content sniffing decides whether it's something we can handle.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    /// Parses comma or semicolon separated lists of patterns.
+    pub fn parse(include: &str, exclude: &str) -> Self {
+        let patterns = |list: &str| {
+            list.split([',', ';'])
+                .filter(|pattern| !pattern.trim().is_empty())
+                .map(Pattern::parse)
+                .collect()
+        };
+        Self {
+            include: patterns(include),
+            exclude: patterns(exclude),
+        }
+    }
+
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|pattern| pattern.matches(relative))
+    }
+
+    pub fn accepts(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(relative)))
+            && !self.is_excluded(relative)
+    }
+}
+
+impl Display for FileFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .map(|pattern| pattern.source.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        write!(
+            f,
+            "include=[{}] exclude=[{}]",
+            list(&self.include),
+            list(&self.exclude)
+        )
+    }
+}
+
+impl Hash for FileFilter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_string().hash(state);
+    }
+}
+
+/// Limits a run to the photos that match all of the conditions that are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PhotoFilter {
+    pub from: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    /// Matched against the make and model together, ignoring case
+    pub camera: Option<String>,
+    /// Also keeps everything else, videos included, out of the run from the start
+    pub motion_only: bool,
+    pub gps_only: bool,
+    /// The width and height a photo needs at least, in either orientation
+    pub min_resolution: Option<(u32, u32)>,
+}
+
+impl PhotoFilter {
+    /// Parses the dates as `YYYY-MM-DD` and the resolution as `<width>x<height>`. Empty fields
+    /// leave that condition out.
+    pub fn parse(
+        from: &str,
+        until: &str,
+        camera: &str,
+        min_resolution: &str,
+        motion_only: bool,
+        gps_only: bool,
+    ) -> Result<Self, String> {
+        let date = |date: &str| match date.trim() {
+            "" => Ok(None),
+            date => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| format!("{} is not a date like 2021-12-31", date)),
+        };
+        let min_resolution = match min_resolution.trim() {
+            "" => None,
+            resolution => {
+                let invalid = || format!("{} is not a resolution like 1920x1080", resolution);
+                let (width, height) = resolution.split_once(['x', 'X']).ok_or_else(invalid)?;
+                let width = width.trim().parse().map_err(|_| invalid())?;
+                let height = height.trim().parse().map_err(|_| invalid())?;
+                Some((width, height))
+            }
+        };
+        let camera = camera.trim();
+        Ok(Self {
+            from: date(from)?,
+            until: date(until)?,
+            camera: Some(camera.to_lowercase()).filter(|_| !camera.is_empty()),
+            motion_only,
+            gps_only,
+            min_resolution,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether a photo taken on `date` with `exif` passes. Photos that lack what a condition
+    /// asks about don't.
+    pub fn accepts(&self, date: Option<NaiveDate>, exif: Option<&Exif>, has_motion: bool) -> bool {
+        if self.motion_only && !has_motion {
+            return false;
+        }
+        if self.from.is_some() || self.until.is_some() {
+            let date = match date {
+                Some(date) => date,
+                None => return false,
+            };
+            if matches!(self.from, Some(from) if date < from)
+                || matches!(self.until, Some(until) if date > until)
+            {
+                return false;
+            }
+        }
+
+        if let Some(camera) = &self.camera {
+            if !camera_name(exif).contains(camera.as_str()) {
+                return false;
+            }
+        }
+        if self.gps_only
+            && exif
+                .and_then(|exif| exif.get_field(Tag::GPSLatitude, In::PRIMARY))
+                .is_none()
+        {
+            return false;
+        }
+        if let Some((min_width, min_height)) = self.min_resolution {
+            let dimension = |tags: [Tag; 2]| {
+                tags.iter()
+                    .find_map(|tag| exif?.get_field(*tag, In::PRIMARY)?.value.get_uint(0))
+            };
+            let width = dimension([Tag::PixelXDimension, Tag::ImageWidth]);
+            let height = dimension([Tag::PixelYDimension, Tag::ImageLength]);
+            let (long, short) = match (width, height) {
+                (Some(width), Some(height)) => (width.max(height), width.min(height)),
+                _ => return false,
+            };
+            if long < min_width.max(min_height) || short < min_width.min(min_height) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The make and model of the camera together, in lower case, or nothing if they're unknown.
+pub fn camera_name(exif: Option<&Exif>) -> String {
+    let text = |tag| {
+        let field = exif?.get_field(tag, In::PRIMARY)?;
+        Some(
+            field
+                .display_value()
+                .to_string()
+                .trim_matches('"')
+                .trim()
+                .to_string(),
+        )
+    };
+    format!(
+        "{} {}",
+        text(Tag::Make).unwrap_or_default(),
+        text(Tag::Model).unwrap_or_default()
+    )
+    .to_lowercase()
+}
+
+impl Display for PhotoFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "from={:?} until={:?} camera={:?} motion_only={} gps_only={} min_resolution={:?}",
+            self.from,
+            self.until,
+            self.camera,
+            self.motion_only,
+            self.gps_only,
+            self.min_resolution
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use exif::experimental::Writer;
+    use exif::{Field, Value};
+
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Pattern::parse(pattern).matches(Path::new(path))
+    }
+
+    fn exif(fields: &[Field]) -> Exif {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        exif::Reader::new().read_raw(tiff.into_inner()).unwrap()
+    }
+
+    fn ascii(tag: Tag, text: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        }
+    }
+
+    fn long(tag: Tag, value: u32) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Long(vec![value]),
+        }
+    }
+
+    fn date(text: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+    }
+
+    fn photo_filter(from: &str, until: &str, camera: &str, resolution: &str) -> PhotoFilter {
+        PhotoFilter::parse(from, until, camera, resolution, false, false).unwrap()
+    }
+
+    #[test]
+    fn sniffs_by_content() {
+        assert_eq!(FileKind::sniff(b"\xFF\xD8\xFF\xE1"), Some(FileKind::Jpeg));
+        assert_eq!(FileKind::sniff(b"\x89PNG\r\n\x1a\n"), Some(FileKind::Png));
+        assert_eq!(
+            FileKind::sniff(b"\0\0\0\x14ftypheic\0\0\0\0mif1"),
+            Some(FileKind::Heif)
+        );
+        assert_eq!(
+            FileKind::sniff(b"\0\0\0\x14ftypisom\0\0\0\0mp42"),
+            Some(FileKind::Video)
+        );
+        assert_eq!(FileKind::sniff(b"\0\0\0\x08moov"), Some(FileKind::Video));
+        assert_eq!(FileKind::sniff(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn matches_extensions_ignoring_case() {
+        assert!(matches("jpg", "a/IMG_1.JPG"));
+        assert!(matches(".jpg", "IMG_1.jpg"));
+        assert!(matches("*.jpg", "a/b/IMG_1.jpg"));
+        assert!(!matches("jpg", "IMG_1.jpeg"));
+        assert!(!matches("jpg", "jpg"));
+    }
+
+    #[test]
+    fn matches_globs_against_the_name() {
+        assert!(matches("IMG_*", "a/img_1.jpg"));
+        assert!(matches("IMG_?.jpg", "IMG_1.jpg"));
+        assert!(!matches("IMG_?.jpg", "IMG_12.jpg"));
+        // Dots and other regex characters are taken literally
+        assert!(!matches("IMG_1.j*", "IMG_1xjpg"));
+        assert!(matches("*.tar.gz", "a.tar.gz"));
+    }
+
+    #[test]
+    fn matches_globs_with_a_slash_against_the_path() {
+        assert!(matches("**/thumbnails/**", "a/b/thumbnails/1.jpg"));
+        assert!(matches("**/thumbnails/**", "thumbnails/1.jpg"));
+        assert!(matches("**/thumbnails/**", "thumbnails"));
+        assert!(!matches("**/thumbnails/**", "a/thumbnails2/1.jpg"));
+        assert!(matches("a/*.jpg", "a/1.jpg"));
+        assert!(!matches("a/*.jpg", "a/b/1.jpg"));
+        assert!(matches("a/**.jpg", "a/b/1.jpg"));
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filter = FileFilter::parse("jpg; heic, ", "**/trash/**");
+        assert!(filter.accepts(Path::new("a/1.jpg")));
+        assert!(filter.accepts(Path::new("1.HEIC")));
+        assert!(!filter.accepts(Path::new("1.png")));
+        assert!(!filter.accepts(Path::new("trash/1.jpg")));
+        assert!(filter.is_excluded(Path::new("a/trash")));
+        assert_eq!(
+            filter.to_string(),
+            "include=[jpg,heic] exclude=[**/trash/**]"
+        );
+    }
+
+    #[test]
+    fn accepts_everything_without_includes() {
+        let filter = FileFilter::parse("", "");
+        assert!(filter.accepts(Path::new("anything.bin")));
+    }
+
+    #[test]
+    fn parses_the_photo_filter() {
+        assert!(photo_filter("", " ", "", "").is_empty());
+        let filter = photo_filter("2020-01-01", "", " Pixel 5 ", "1920 x 1080");
+        assert_eq!(filter.from, date("2020-01-01"));
+        assert_eq!(filter.camera.as_deref(), Some("pixel 5"));
+        assert_eq!(filter.min_resolution, Some((1920, 1080)));
+        assert!(PhotoFilter::parse("2020-13-01", "", "", "", false, false).is_err());
+        assert!(PhotoFilter::parse("", "", "", "1920", false, false).is_err());
+    }
+
+    #[test]
+    fn keeps_photos_within_the_dates() {
+        let filter = photo_filter("2020-01-01", "2020-12-31", "", "");
+        assert!(filter.accepts(date("2020-01-01"), None, false));
+        assert!(filter.accepts(date("2020-12-31"), None, false));
+        assert!(!filter.accepts(date("2019-12-31"), None, false));
+        assert!(!filter.accepts(date("2021-01-01"), None, false));
+        assert!(!filter.accepts(None, None, false));
+        let filter = photo_filter("", "2020-12-31", "", "");
+        assert!(filter.accepts(date("1990-01-01"), None, false));
+    }
+
+    #[test]
+    fn matches_the_camera_by_make_and_model() {
+        let photo = exif(&[ascii(Tag::Make, "Google"), ascii(Tag::Model, "Pixel 5")]);
+        assert_eq!(camera_name(Some(&photo)), "google pixel 5");
+        assert!(photo_filter("", "", "google pixel", "").accepts(None, Some(&photo), false));
+        assert!(!photo_filter("", "", "samsung", "").accepts(None, Some(&photo), false));
+        assert!(!photo_filter("", "", "pixel", "").accepts(None, None, false));
+    }
+
+    #[test]
+    fn needs_motion_and_gps_when_asked() {
+        let motion_only = PhotoFilter::parse("", "", "", "", true, false).unwrap();
+        assert!(motion_only.accepts(None, None, true));
+        assert!(!motion_only.accepts(None, None, false));
+
+        let gps_only = PhotoFilter::parse("", "", "", "", false, true).unwrap();
+        let located = exif(&[Field {
+            tag: Tag::GPSLatitude,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![(52, 1).into(), (0, 1).into(), (0, 1).into()]),
+        }]);
+        assert!(gps_only.accepts(None, Some(&located), false));
+        assert!(!gps_only.accepts(None, Some(&exif(&[ascii(Tag::Make, "Canon")])), false));
+        assert!(!gps_only.accepts(None, None, false));
+    }
+
+    #[test]
+    fn checks_the_resolution_in_either_orientation() {
+        let filter = photo_filter("", "", "", "1920x1080");
+        let portrait = exif(&[
+            long(Tag::PixelXDimension, 1080),
+            long(Tag::PixelYDimension, 1920),
+        ]);
+        assert!(filter.accepts(None, Some(&portrait), false));
+        let small = exif(&[long(Tag::ImageWidth, 1280), long(Tag::ImageLength, 720)]);
+        assert!(!filter.accepts(None, Some(&small), false));
+        assert!(!filter.accepts(None, Some(&exif(&[ascii(Tag::Make, "Canon")])), false));
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use crate::extract::{Motion, MotionFormat};
+use crate::isobmff::{self, BmffBox, ByteReader};
+
+/// Looks for the video of a HEIF motion photo. Samsung stores it in a top level `mpvd` box,
+/// Google appends it as a complete MP4 after the HEIF boxes, and other encoders add it as an
+/// item with a video mime type.
+pub fn find_motion(buf: &[u8]) -> Option<Motion> {
+    let mut boxes = isobmff::boxes(buf, 0..buf.len());
+    let mut meta = None;
+    for found in &mut boxes {
+        if found.is(b"mpvd") {
+            return Some(motion(
+                MotionFormat::SamsungHeic,
+                found.payload().start,
+                found.end,
+            ));
+        }
+        // The boxes of an appended MP4 are valid top level boxes too, its `ftyp` is where it starts
+        if found.is(b"ftyp") && found.start > 0 {
+            return Some(motion(MotionFormat::GoogleHeic, found.start, buf.len()));
+        }
+        if found.is(b"meta") {
+            meta = Some(found);
+        }
+    }
+
+    // An MP4 that can't be walked box by box still starts right after the last HEIF box
+    let trailer = boxes.position();
+    if buf.len() > trailer + 8 && &buf[trailer + 4..trailer + 8] == b"ftyp" {
+        return Some(motion(MotionFormat::GoogleHeic, trailer, buf.len()));
+    }
+
+    meta.and_then(|meta| find_video_item(buf, &meta))
+}
+
+fn motion(format: MotionFormat, start: usize, end: usize) -> Motion {
+    Motion {
+        format,
+        offset: start,
+        length: end - start,
+        presentation_timestamp_us: None,
+    }
+}
+
+fn find_video_item(buf: &[u8], meta: &BmffBox) -> Option<Motion> {
+    let mut videos = Vec::new();
+    let mut locations = HashMap::new();
+    for child in isobmff::full_box_children(buf, meta) {
+        if child.is(b"iinf") {
+            videos = video_items(buf, &child)?;
+        } else if child.is(b"iloc") {
+            locations = item_locations(buf, &child)?;
+        }
+    }
+
+    videos
+        .iter()
+        .filter_map(|id| locations.get(id))
+        .find(|(offset, length)| {
+            let end = offset.checked_add(*length);
+            end.filter(|end| *end <= buf.len()).is_some()
+        })
+        .map(|(offset, length)| motion(MotionFormat::HeifItem, *offset, offset + length))
+}
+
+/// The ids of the items declared with a video mime type in the `iinf` box.
+fn video_items(buf: &[u8], iinf: &BmffBox) -> Option<Vec<u32>> {
+    let mut reader = ByteReader::new(&buf[..iinf.end], iinf.payload().start);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    reader.skip(if version == 0 { 2 } else { 4 })?;
+
+    let mut videos = Vec::new();
+    for infe in isobmff::boxes(buf, reader.position()..iinf.end) {
+        if !infe.is(b"infe") {
+            continue;
+        }
+        let mut reader = ByteReader::new(&buf[..infe.end], infe.payload().start);
+        let version = reader.u8()?;
+        reader.skip(3)?;
+        // Older versions have no item type, so they can't describe a video either
+        if version < 2 {
+            continue;
+        }
+        let id = if version == 2 {
+            reader.u16()? as u32
+        } else {
+            reader.u32()?
+        };
+        reader.skip(2)?;
+        let item_type = reader.kind()?;
+        reader.string()?;
+        if &item_type == b"mime" && reader.string()?.starts_with("video/") {
+            videos.push(id);
+        }
+    }
+    Some(videos)
+}
+
+/// Maps item ids to the offset and length of their data, for items stored as a single extent
+/// in the file itself.
+fn item_locations(buf: &[u8], iloc: &BmffBox) -> Option<HashMap<u32, (usize, usize)>> {
+    let mut reader = ByteReader::new(&buf[..iloc.end], iloc.payload().start);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    let sizes = reader.u8()?;
+    let (offset_size, length_size) = ((sizes >> 4) as usize, (sizes & 0xf) as usize);
+    let sizes = reader.u8()?;
+    let base_offset_size = (sizes >> 4) as usize;
+    let index_size = if version == 1 || version == 2 {
+        (sizes & 0xf) as usize
+    } else {
+        0
+    };
+    let item_count = if version < 2 {
+        reader.u16()? as u32
+    } else {
+        reader.u32()?
+    };
+
+    let mut locations = HashMap::new();
+    for _ in 0..item_count {
+        let id = if version < 2 {
+            reader.u16()? as u32
+        } else {
+            reader.u32()?
+        };
+        let construction_method = if version == 1 || version == 2 {
+            reader.u16()? & 0xf
+        } else {
+            0
+        };
+        reader.skip(2)?;
+        let base_offset = reader.uint(base_offset_size)?;
+        let extent_count = reader.u16()?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            reader.skip(index_size)?;
+            let offset = reader.uint(offset_size)?;
+            let length = reader.uint(length_size)?;
+            extents.push((base_offset.checked_add(offset)?, length));
+        }
+        // Construction method 0 means the offsets point into the file
+        if let ([(offset, length)], 0) = (extents.as_slice(), construction_method) {
+            locations.insert(id, (*offset as usize, *length as usize));
+        }
+    }
+    Some(locations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isobmff::bmff;
+
+    fn heic() -> Vec<u8> {
+        let mut buf = bmff(b"ftyp", b"heic\0\0\0\0mif1heic");
+        buf.extend(bmff(b"meta", &[0; 4]));
+        buf.extend(bmff(b"mdat", b"image"));
+        buf
+    }
+
+    fn mp4() -> Vec<u8> {
+        let mut buf = bmff(b"ftyp", b"isom\0\0\0\0isommp42");
+        buf.extend(bmff(b"moov", &bmff(b"mvhd", &[0; 8])));
+        buf.extend(bmff(b"mdat", b"video"));
+        buf
+    }
+
+    #[test]
+    fn finds_appended_mp4() {
+        let mut buf = heic();
+        let image_len = buf.len();
+        buf.extend(mp4());
+        let motion = find_motion(&buf).unwrap();
+        assert_eq!(motion.format, MotionFormat::GoogleHeic);
+        assert_eq!(motion.video(&buf), &mp4()[..]);
+        assert_eq!(motion.offset, image_len);
+    }
+
+    #[test]
+    fn finds_appended_mp4_that_is_cut_short() {
+        let mut buf = heic();
+        let image_len = buf.len();
+        // The `ftyp` claims more bytes than there are
+        buf.extend_from_slice(&[0, 0, 1, 0]);
+        buf.extend_from_slice(b"ftypisom");
+        let motion = find_motion(&buf).unwrap();
+        assert_eq!(motion.format, MotionFormat::GoogleHeic);
+        assert_eq!(motion.offset, image_len);
+    }
+
+    #[test]
+    fn finds_samsung_box() {
+        let mut buf = heic();
+        let payload_start = buf.len() + 8;
+        buf.extend(bmff(b"mpvd", &mp4()));
+        let motion = find_motion(&buf).unwrap();
+        assert_eq!(motion.format, MotionFormat::SamsungHeic);
+        assert_eq!(motion.offset, payload_start);
+        assert_eq!(motion.video(&buf), &mp4()[..]);
+    }
+
+    #[test]
+    fn finds_video_item() {
+        let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+        infe.extend_from_slice(b"mime\0video/mp4\0");
+        let mut iinf = vec![0, 0, 0, 0, 0, 1];
+        iinf.extend(bmff(b"infe", &infe));
+
+        let mut buf = bmff(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let iloc_len = 8 + 4 + 2 + 2 + 2 + 2 + 2 + 4 + 4;
+        let meta_len = 8 + 4 + 8 + iinf.len() + iloc_len;
+        let video_at = (buf.len() + meta_len + 8) as u32;
+        let video = mp4();
+        let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1];
+        iloc.extend_from_slice(&video_at.to_be_bytes());
+        iloc.extend_from_slice(&(video.len() as u32).to_be_bytes());
+        let mut meta = vec![0; 4];
+        meta.extend(bmff(b"iinf", &iinf));
+        meta.extend(bmff(b"iloc", &iloc));
+        buf.extend(bmff(b"meta", &meta));
+        buf.extend(bmff(b"mdat", &video));
+
+        let motion = find_motion(&buf).unwrap();
+        assert_eq!(motion.format, MotionFormat::HeifItem);
+        assert_eq!(motion.video(&buf), &video[..]);
+    }
+
+    #[test]
+    fn still_photo_has_no_motion() {
+        assert!(find_motion(&heic()).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// A box of the ISO base media file format, which both HEIF images and MP4 videos are made of.
+/// Offsets are absolute within the buffer the box was read from.
+#[derive(Debug, Clone, Copy)]
+pub struct BmffBox {
+    pub kind: [u8; 4],
+    pub start: usize,
+    pub header_len: usize,
+    pub end: usize,
+}
+
+impl BmffBox {
+    pub fn is(&self, kind: &[u8; 4]) -> bool {
+        &self.kind == kind
+    }
+
+    pub fn payload(&self) -> Range<usize> {
+        self.start + self.header_len..self.end
+    }
+}
+
+/// Iterates over the boxes laid out back to back in `buf[range]`. Iteration stops at the first
+/// box that doesn't fit, after which `position` points at the first byte that isn't a box.
+pub struct Boxes<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Boxes<'a> {
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = BmffBox;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut reader = ByteReader::new(&self.buf[..self.end], self.pos);
+        let size = reader.u32()? as u64;
+        let kind = reader.kind()?;
+        let size = match size {
+            0 => (self.end - self.pos) as u64,
+            1 => reader.u64()?,
+            size => size,
+        };
+        let header_len = reader.position() - self.pos;
+        let end = self.pos as u64 + size;
+        if size < header_len as u64 || end > self.end as u64 {
+            return None;
+        }
+
+        let found = BmffBox {
+            kind,
+            start: self.pos,
+            header_len,
+            end: end as usize,
+        };
+        self.pos = found.end;
+        Some(found)
+    }
+}
+
+pub fn boxes(buf: &[u8], range: Range<usize>) -> Boxes<'_> {
+    Boxes {
+        buf,
+        pos: range.start,
+        end: range.end.min(buf.len()),
+    }
+}
+
+pub fn children<'a>(buf: &'a [u8], parent: &BmffBox) -> Boxes<'a> {
+    boxes(buf, parent.payload())
+}
+
+/// Children of a "full box", which has a version and flags in front of its children.
+pub fn full_box_children<'a>(buf: &'a [u8], parent: &BmffBox) -> Boxes<'a> {
+    let payload = parent.payload();
+    boxes(buf, (payload.start + 4).min(payload.end)..payload.end)
+}
+
+pub fn find_box(buf: &[u8], range: Range<usize>, kind: &[u8; 4]) -> Option<BmffBox> {
+    boxes(buf, range).find(|found| found.is(kind))
+}
+
+/// Reads the top level box of `kind` out of `file`, seeking past the boxes in front of it rather
+/// than reading them. Returns `None` if there's no such box.
+pub fn read_box<R: Read + Seek>(mut file: R, kind: &[u8; 4]) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = vec![0; 8];
+        match file.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            read => read?,
+        }
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // Open ended, so this is the last box
+            0 if &header[4..] == kind => {
+                file.read_to_end(&mut header)?;
+                return Ok(Some(header));
+            }
+            0 => return Ok(None),
+            1 => {
+                let mut large = [0; 8];
+                file.read_exact(&mut large)?;
+                header.extend_from_slice(&large);
+                u64::from_be_bytes(large)
+            }
+            size => size as u64,
+        };
+        let rest = match size.checked_sub(header.len() as u64) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+        if &header[4..8] == kind {
+            file.take(rest).read_to_end(&mut header)?;
+            return Ok(Some(header));
+        }
+        match i64::try_from(rest) {
+            Ok(rest) => file.seek(SeekFrom::Current(rest))?,
+            Err(_) => return Ok(None),
+        };
+    }
+}
+
+pub fn is_heif(buf: &[u8]) -> bool {
+    const BRANDS: [&[u8; 4]; 8] = [
+        b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+    ];
+    has_brand(buf, &BRANDS)
+}
+
+/// Whether `buf` starts with a `ftyp` box that lists one of `brands` as major or compatible brand.
+pub fn has_brand(buf: &[u8], brands: &[&[u8; 4]]) -> bool {
+    let ftyp = match boxes(buf, 0..buf.len()).next() {
+        Some(ftyp) if ftyp.is(b"ftyp") => ftyp,
+        _ => return false,
+    };
+    let payload = &buf[ftyp.payload()];
+    // Major brand, minor version and then the compatible brands
+    payload
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(idx, _)| *idx != 1)
+        .any(|(_, brand)| brands.iter().any(|wanted| &wanted[..] == brand))
+}
+
+/// Reads the big endian integers boxes are made of, returning `None` when running out of data.
+pub struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(buf: &'a [u8], pos: usize) -> Self {
+        Self { buf, pos }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    /// Reads an unsigned integer of `len` bytes, as used by fields with a configurable size.
+    pub fn uint(&mut self, len: usize) -> Option<u64> {
+        if len > 8 {
+            return None;
+        }
+        Some(
+            self.bytes(len)?
+                .iter()
+                .fold(0, |value, byte| value << 8 | *byte as u64),
+        )
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.uint(1).map(|value| value as u8)
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.uint(2).map(|value| value as u16)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.uint(4).map(|value| value as u32)
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.uint(8)
+    }
+
+    pub fn kind(&mut self) -> Option<[u8; 4]> {
+        let mut kind = [0; 4];
+        kind.copy_from_slice(self.bytes(4)?);
+        Some(kind)
+    }
+
+    /// Reads a null terminated string.
+    pub fn string(&mut self) -> Option<String> {
+        let rest = self.buf.get(self.pos..)?;
+        let len = rest.iter().position(|byte| *byte == 0)?;
+        let string = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Some(string)
+    }
+}
+
+/// Lays out a box around `payload`, to build files from in tests.
+#[cfg(test)]
+pub fn bmff(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut found = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    found.extend_from_slice(kind);
+    found.extend_from_slice(payload);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_boxes_until_one_does_not_fit() {
+        let mut buf = bmff(b"ftyp", b"isom");
+        buf.extend(bmff(b"free", b""));
+        buf.extend_from_slice(&[0, 0, 0, 64]);
+        buf.extend_from_slice(b"mdat");
+        let mut found = boxes(&buf, 0..buf.len());
+        let kinds: Vec<[u8; 4]> = found.by_ref().map(|found| found.kind).collect();
+        assert_eq!(kinds, vec![*b"ftyp", *b"free"]);
+        assert_eq!(found.position(), 20);
+    }
+
+    #[test]
+    fn reads_large_and_open_ended_sizes() {
+        let mut buf = vec![0, 0, 0, 1];
+        buf.extend_from_slice(b"mdat");
+        buf.extend_from_slice(&20u64.to_be_bytes());
+        buf.extend_from_slice(b"data");
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+        buf.extend_from_slice(b"free");
+        buf.extend_from_slice(b"rest");
+        let found: Vec<BmffBox> = boxes(&buf, 0..buf.len()).collect();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].header_len, 16);
+        assert_eq!(&buf[found[0].payload()], b"data");
+        assert_eq!(&buf[found[1].payload()], b"rest");
+    }
+
+    #[test]
+    fn rejects_sizes_smaller_than_the_header() {
+        let mut buf = vec![0, 0, 0, 4];
+        buf.extend_from_slice(b"free");
+        assert!(boxes(&buf, 0..buf.len()).next().is_none());
+    }
+
+    #[test]
+    fn matches_major_and_compatible_brands() {
+        assert!(is_heif(&bmff(b"ftyp", b"heic\0\0\0\0")));
+        assert!(is_heif(&bmff(b"ftyp", b"isom\0\0\0\0mif1")));
+        // The minor version isn't a brand
+        assert!(!is_heif(&bmff(b"ftyp", b"isomheicmp42")));
+        assert!(!is_heif(&bmff(b"moov", b"heic")));
+    }
+
+    #[test]
+    fn reads_a_box_without_the_ones_in_front() {
+        let mut buf = bmff(b"ftyp", b"isom");
+        buf.extend_from_slice(&[0, 0, 0, 1]);
+        buf.extend_from_slice(b"mdat");
+        buf.extend_from_slice(&20u64.to_be_bytes());
+        buf.extend_from_slice(b"data");
+        buf.extend(bmff(b"moov", b"header"));
+        let read = |kind| read_box(io::Cursor::new(&buf), kind).unwrap();
+        assert_eq!(read(b"moov"), Some(bmff(b"moov", b"header")));
+        assert_eq!(read(b"ftyp"), Some(bmff(b"ftyp", b"isom")));
+        assert_eq!(read(b"meta"), None);
+    }
+
+    #[test]
+    fn reader_stops_at_the_end() {
+        let buf = [0, 1, 2, b'a', 0];
+        let mut reader = ByteReader::new(&buf, 0);
+        assert_eq!(reader.u16(), Some(1));
+        assert_eq!(reader.u8(), Some(2));
+        assert_eq!(reader.string().as_deref(), Some("a"));
+        assert_eq!(reader.u8(), None);
+        assert_eq!(reader.uint(9), None);
+    }
+}
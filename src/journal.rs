@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::fs::{read_to_string, remove_file, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_NAME: &str = ".motionsplit-journal";
+
+/// Records which source files a run has fully processed, so a run that was interrupted can be
+/// resumed by a later run with the same settings.
+pub struct Journal {
+    path: PathBuf,
+    completed: HashSet<PathBuf>,
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal in `dir`. Entries left behind by an earlier run are only kept if that
+    /// run used the same `settings`, otherwise the journal starts over.
+    pub fn open(dir: impl AsRef<Path>, settings: &str) -> io::Result<Self> {
+        let path = dir.as_ref().join(JOURNAL_NAME);
+        let header = format!("# {}", settings.replace('\n', " "));
+
+        let contents = read_to_string(&path).unwrap_or_default();
+        let mut lines = contents.lines();
+        let completed: HashSet<PathBuf> = if lines.next() == Some(header.as_str()) {
+            lines.map(PathBuf::from).collect()
+        } else {
+            HashSet::new()
+        };
+
+        let file = if completed.is_empty() {
+            let mut file = File::create(&path)?;
+            writeln!(file, "{}", header)?;
+            file
+        } else {
+            let mut file = OpenOptions::new().append(true).open(&path)?;
+            // A crash halfway through a write leaves a partial line, don't glue the next entry to it
+            if !contents.ends_with('\n') {
+                writeln!(file)?;
+            }
+            file
+        };
+
+        Ok(Self {
+            path,
+            completed,
+            file,
+        })
+    }
+
+    pub fn is_completed(&self, source: &Path) -> bool {
+        self.completed.contains(source)
+    }
+
+    pub fn complete(&mut self, source: &Path) -> io::Result<()> {
+        writeln!(self.file, "{}", source.to_string_lossy())?;
+        self.file.sync_data()?;
+        self.completed.insert(source.to_path_buf());
+        Ok(())
+    }
+
+    /// Removes the journal once the whole run went through, so the next run starts fresh.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        remove_file(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "motionsplit-journal-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resumes_with_the_same_settings() {
+        let dir = dir("resume");
+        let mut journal = Journal::open(&dir, "a -> b").unwrap();
+        journal.complete(Path::new("a/1.jpg")).unwrap();
+        drop(journal);
+
+        let mut journal = Journal::open(&dir, "a -> b").unwrap();
+        assert!(journal.is_completed(Path::new("a/1.jpg")));
+        assert!(!journal.is_completed(Path::new("a/2.jpg")));
+        journal.complete(Path::new("a/2.jpg")).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&dir, "a -> b").unwrap();
+        assert!(journal.is_completed(Path::new("a/1.jpg")));
+        assert!(journal.is_completed(Path::new("a/2.jpg")));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn starts_over_with_other_settings() {
+        let dir = dir("settings");
+        let mut journal = Journal::open(&dir, "a -> b").unwrap();
+        journal.complete(Path::new("a/1.jpg")).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&dir, "a -> c").unwrap();
+        assert!(!journal.is_completed(Path::new("a/1.jpg")));
+        drop(journal);
+        // The journal of the other settings is gone as well
+        let journal = Journal::open(&dir, "a -> b").unwrap();
+        assert!(!journal.is_completed(Path::new("a/1.jpg")));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_entries_apart_from_a_partial_line() {
+        let dir = dir("partial");
+        write(dir.join(JOURNAL_NAME), "# a -> b\na/1.jpg\na/2.j").unwrap();
+        let mut journal = Journal::open(&dir, "a -> b").unwrap();
+        journal.complete(Path::new("a/3.jpg")).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&dir, "a -> b").unwrap();
+        assert!(journal.is_completed(Path::new("a/1.jpg")));
+        assert!(journal.is_completed(Path::new("a/3.jpg")));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finishing_removes_the_journal() {
+        let dir = dir("finish");
+        let journal = Journal::open(&dir, "a -> b").unwrap();
+        journal.finish().unwrap();
+        assert!(!dir.join(JOURNAL_NAME).exists());
+        remove_dir_all(dir).unwrap();
+    }
+}
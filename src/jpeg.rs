@@ -0,0 +1,158 @@
+use std::convert::TryFrom;
+use std::ops::Range;
+
+pub const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+pub const APP1: u8 = 0xE1;
+
+/// A marker segment in the header of a JPEG, up to the start of the image data.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub marker: u8,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Segment {
+    /// The data following the marker and length.
+    pub fn payload(&self) -> Range<usize> {
+        self.start + 4..self.end
+    }
+}
+
+pub fn is_jpeg(buf: &[u8]) -> bool {
+    buf.starts_with(&[0xFF, SOI, 0xFF])
+}
+
+/// The segments in front of the first scan, or `None` if the header is malformed.
+pub fn segments(buf: &[u8]) -> Option<Vec<Segment>> {
+    if !is_jpeg(buf) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        while buf.get(pos) == Some(&0xFF) && buf.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if *buf.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *buf.get(pos + 1)?;
+        let len = u16::from_be_bytes([*buf.get(pos + 2)?, *buf.get(pos + 3)?]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > buf.len() {
+            return None;
+        }
+        segments.push(Segment {
+            marker,
+            start: pos,
+            end,
+        });
+        if marker == SOS {
+            return Some(segments);
+        }
+        pos = end;
+    }
+}
+
+/// The length of the JPEG image itself, up to and including its end of image marker. Anything
+/// after it is a trailer, like the video of a motion photo.
+pub fn image_len(buf: &[u8]) -> Option<usize> {
+    let scan = segments(buf)?.last()?.end;
+    // Within the image data an 0xFF byte is always followed by a zero byte or a marker, and the
+    // only marker that can be an end of image is the real one
+    (scan..buf.len().saturating_sub(1))
+        .find(|idx| buf[*idx] == 0xFF && buf[idx + 1] == EOI)
+        .map(|idx| idx + 2)
+}
+
+/// The XMP packet of the JPEG, if it has one.
+pub fn xmp(buf: &[u8]) -> Option<&[u8]> {
+    segments(buf)?
+        .into_iter()
+        .filter(|segment| segment.marker == APP1)
+        .map(|segment| &buf[segment.payload()])
+        .find(|payload| payload.starts_with(XMP_NAMESPACE))
+        .map(|payload| &payload[XMP_NAMESPACE.len()..])
+}
+
+/// Builds an APP1 segment holding `header` followed by `data`.
+pub fn app1_segment(header: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let len = u16::try_from(2 + header.len() + data.len()).ok()?;
+    let mut segment = vec![0xFF, APP1];
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(header);
+    segment.extend_from_slice(data);
+    Some(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![0xFF, SOI];
+        for segment in segments {
+            buf.extend_from_slice(segment);
+        }
+        buf.extend_from_slice(&[0xFF, SOS, 0, 2]);
+        // Scan data with a stuffed 0xFF byte, which isn't a marker
+        buf.extend_from_slice(&[1, 0xFF, 0, 2, 0xFF, EOI]);
+        buf
+    }
+
+    #[test]
+    fn reads_segments_up_to_the_scan() {
+        let app0 = vec![0xFF, 0xE0, 0, 4, b'J', b'F'];
+        let buf = jpeg(&[app0, app1_segment(EXIF_HEADER, b"tiff").unwrap()]);
+        let segments = segments(&buf).unwrap();
+        let markers: Vec<u8> = segments.iter().map(|segment| segment.marker).collect();
+        assert_eq!(markers, vec![0xE0, APP1, SOS]);
+        assert_eq!(&buf[segments[0].payload()], b"JF");
+        assert_eq!(segments[2].start, 2 + 6 + 14);
+    }
+
+    #[test]
+    fn skips_fill_bytes() {
+        let mut buf = vec![0xFF, SOI, 0xFF, 0xFF];
+        buf.extend_from_slice(&[0xFF, SOS, 0, 2, 0xFF, EOI]);
+        let segments = segments(&buf).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].start, 4);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(segments(b"not a jpeg").is_none());
+        // Runs past the end
+        assert!(segments(&[0xFF, SOI, 0xFF, 0xE0, 0, 16, 0]).is_none());
+        // Too short to hold its own length
+        assert!(segments(&[0xFF, SOI, 0xFF, 0xE0, 0, 1, 0xFF, SOS, 0, 2]).is_none());
+        // No scan
+        assert!(segments(&[0xFF, SOI, 0xFF, 0xE0, 0, 2]).is_none());
+    }
+
+    #[test]
+    fn image_ends_at_its_end_of_image_marker() {
+        let mut buf = jpeg(&[]);
+        let len = buf.len();
+        buf.extend_from_slice(b"\0\0\0\x18ftypisom");
+        assert_eq!(image_len(&buf), Some(len));
+    }
+
+    #[test]
+    fn finds_xmp() {
+        let buf = jpeg(&[
+            app1_segment(EXIF_HEADER, b"tiff").unwrap(),
+            app1_segment(XMP_NAMESPACE, b"<x:xmpmeta/>").unwrap(),
+        ]);
+        assert_eq!(xmp(&buf), Some(&b"<x:xmpmeta/>"[..]));
+        assert_eq!(xmp(&jpeg(&[])), None);
+    }
+}
@@ -0,0 +1,205 @@
+use exif::{Exif, In, Tag, Value};
+
+use crate::isobmff::{self, BmffBox, ByteReader};
+
+const MAKER_NOTE_HEADER: &[u8] = b"Apple iOS\0";
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+const CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+
+/// The identifier that links the still of an Apple Live Photo to its video, as stored in the
+/// Apple MakerNote of the still.
+pub fn still_identifier(exif: &Exif) -> Option<String> {
+    let note = match &exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        Value::Undefined(note, _) => note,
+        _ => return None,
+    };
+    if !note.starts_with(MAKER_NOTE_HEADER) {
+        return None;
+    }
+
+    // The header is followed by a version and the byte order, after which the IFD starts.
+    // Offsets within the IFD are relative to the start of the MakerNote.
+    let header = MAKER_NOTE_HEADER.len();
+    let big_endian = match note.get(header + 2..header + 4)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let int = |at: usize, len: usize| -> Option<u32> {
+        let bytes = note.get(at..at + len)?;
+        let fold = |value: u32, byte: &u8| value << 8 | *byte as u32;
+        Some(if big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    };
+
+    let ifd = header + 4;
+    let count = int(ifd, 2)? as usize;
+    (0..count)
+        .map(|idx| ifd + 2 + idx * 12)
+        .find(|entry| int(*entry, 2) == Some(CONTENT_IDENTIFIER_TAG as u32))
+        .and_then(|entry| {
+            let len = int(entry + 4, 4)? as usize;
+            let start = if len <= 4 {
+                entry + 8
+            } else {
+                int(entry + 8, 4)? as usize
+            };
+            let value = note.get(start..start.checked_add(len)?)?;
+            let value = String::from_utf8_lossy(value);
+            Some(value.trim_end_matches('\0').to_string())
+        })
+        .filter(|identifier| !identifier.is_empty())
+}
+
+/// The identifier that links the video of an Apple Live Photo to its still, as stored in the
+/// QuickTime metadata of the movie.
+pub fn video_identifier(buf: &[u8]) -> Option<String> {
+    let moov = isobmff::find_box(buf, 0..buf.len(), b"moov")?;
+    let meta = isobmff::find_box(buf, moov.payload(), b"meta")?;
+    // QuickTime's meta box lacks the version and flags of the ISO one, but accept both
+    let mut children: Vec<BmffBox> = isobmff::children(buf, &meta).collect();
+    if !children.iter().any(|child| child.is(b"keys")) {
+        children = isobmff::full_box_children(buf, &meta).collect();
+    }
+    let keys = children.iter().find(|child| child.is(b"keys"))?;
+    let ilst = children.iter().find(|child| child.is(b"ilst"))?;
+
+    let mut reader = ByteReader::new(&buf[..keys.end], keys.payload().start);
+    reader.skip(4)?;
+    let count = reader.u32()?;
+    let mut index = None;
+    for key in 1..=count {
+        let size = reader.u32()? as usize;
+        reader.skip(4)?;
+        if reader.bytes(size.checked_sub(8)?)? == CONTENT_IDENTIFIER_KEY {
+            index = Some(key);
+            break;
+        }
+    }
+    let index = index?;
+
+    // The items in the list are named after the (1-based) index of their key
+    let item = isobmff::children(buf, ilst).find(|item| u32::from_be_bytes(item.kind) == index)?;
+    let data = isobmff::find_box(buf, item.payload(), b"data")?;
+    // Skip the type indicator and locale in front of the value
+    let value = buf.get(data.payload().start + 8..data.end)?;
+    Some(String::from_utf8_lossy(value).into_owned()).filter(|identifier| !identifier.is_empty())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+
+    use exif::experimental::Writer;
+    use exif::{Field, Reader};
+
+    use super::*;
+    use crate::isobmff::bmff;
+
+    pub const IDENTIFIER: &str = "2F9C1A36-8E1B-4C51-A1B0-9C4E7A6A3D10";
+
+    /// An Apple MakerNote with the identifier, stored past the IFD like the camera does.
+    pub fn maker_note(identifier: &str) -> Vec<u8> {
+        let mut note = MAKER_NOTE_HEADER.to_vec();
+        note.extend_from_slice(&[0, 1]);
+        note.extend_from_slice(b"MM");
+        note.extend_from_slice(&2u16.to_be_bytes());
+        // Another tag in front, with a value that fits in the entry
+        note.extend_from_slice(&[0, 0x01, 0, 9, 0, 0, 0, 1, 0, 0, 0, 7]);
+        let value_at = note.len() + 12;
+        let len = identifier.len() as u32 + 1;
+        note.extend_from_slice(&CONTENT_IDENTIFIER_TAG.to_be_bytes());
+        note.extend_from_slice(&2u16.to_be_bytes());
+        note.extend_from_slice(&len.to_be_bytes());
+        note.extend_from_slice(&(value_at as u32).to_be_bytes());
+        note.extend_from_slice(identifier.as_bytes());
+        note.push(0);
+        note
+    }
+
+    fn exif(note: Vec<u8>) -> Exif {
+        let field = Field {
+            tag: Tag::MakerNote,
+            ifd_num: In::PRIMARY,
+            value: Value::Undefined(note, 0),
+        };
+        let mut writer = Writer::new();
+        writer.push_field(&field);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        Reader::new().read_raw(tiff.into_inner()).unwrap()
+    }
+
+    /// A movie with QuickTime metadata, holding `identifier` under the last of its keys.
+    pub fn movie(identifier: &str, iso_meta: bool) -> Vec<u8> {
+        let key = |name: &[u8]| {
+            let mut key = ((name.len() + 8) as u32).to_be_bytes().to_vec();
+            key.extend_from_slice(b"mdta");
+            key.extend_from_slice(name);
+            key
+        };
+        let mut keys = vec![0; 4];
+        keys.extend_from_slice(&2u32.to_be_bytes());
+        keys.extend(key(b"com.apple.quicktime.make"));
+        keys.extend(key(CONTENT_IDENTIFIER_KEY));
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(identifier.as_bytes());
+        let mut ilst = bmff(
+            &1u32.to_be_bytes(),
+            &bmff(b"data", b"\0\0\0\x01\0\0\0\0Apple"),
+        );
+        ilst.extend(bmff(&2u32.to_be_bytes(), &bmff(b"data", &data)));
+
+        let mut meta = if iso_meta { vec![0; 4] } else { Vec::new() };
+        meta.extend(bmff(b"keys", &keys));
+        meta.extend(bmff(b"ilst", &ilst));
+        let mut buf = bmff(b"ftyp", b"qt  \0\0\0\0qt  ");
+        buf.extend(bmff(b"moov", &bmff(b"meta", &meta)));
+        buf
+    }
+
+    #[test]
+    fn reads_the_identifier_from_the_maker_note() {
+        let exif = exif(maker_note(IDENTIFIER));
+        assert_eq!(still_identifier(&exif).as_deref(), Some(IDENTIFIER));
+    }
+
+    #[test]
+    fn ignores_other_maker_notes() {
+        let mut note = maker_note(IDENTIFIER);
+        note[..5].copy_from_slice(b"Nikon");
+        assert!(still_identifier(&exif(note)).is_none());
+        assert!(still_identifier(&exif(maker_note(""))).is_none());
+        // The identifier points past the end of the note
+        let mut note = maker_note(IDENTIFIER);
+        note.truncate(note.len() - 10);
+        assert!(still_identifier(&exif(note)).is_none());
+    }
+
+    #[test]
+    fn reads_the_identifier_from_the_movie_keys() {
+        assert_eq!(
+            video_identifier(&movie(IDENTIFIER, false)).as_deref(),
+            Some(IDENTIFIER)
+        );
+        assert_eq!(
+            video_identifier(&movie(IDENTIFIER, true)).as_deref(),
+            Some(IDENTIFIER)
+        );
+    }
+
+    #[test]
+    fn needs_the_content_identifier_key() {
+        let mut buf = movie(IDENTIFIER, false);
+        let at = buf
+            .windows(CONTENT_IDENTIFIER_KEY.len())
+            .position(|window| window == CONTENT_IDENTIFIER_KEY)
+            .unwrap();
+        buf[at] = b'x';
+        assert!(video_identifier(&buf).is_none());
+        assert!(video_identifier(&bmff(b"moov", b"")).is_none());
+    }
+}
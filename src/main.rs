@@ -8,62 +8,479 @@ use iced::{
 use std::env;
 use std::fmt::Display;
 use std::fs::canonicalize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+mod combine;
+mod date;
+mod error;
+mod exif_writer;
 mod extract;
 mod file_task;
+mod filter;
+mod heif;
+mod isobmff;
+mod journal;
+mod jpeg;
+mod live_photo;
+mod mp4;
+mod naming;
+mod preview;
+mod progress;
+mod report;
+mod review;
+mod shift;
+mod sidecar;
+mod takeout;
 
 fn main() {
-    let path = env::args().nth(1);
-    match path {
-        Some(s) => extract::extract_mp4(s).unwrap(),
-        None => open_ui().unwrap(),
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => open_ui().unwrap(),
+        [command, format, still, video, output] if command == "combine" => {
+            match parse_format(format) {
+                Some(format) => {
+                    exit_on_error(still, combine::combine_files(still, video, output, format))
+                }
+                None => usage(),
+            }
+        }
+        [command, format, photo, output] if command == "convert" => match parse_format(format) {
+            Some(format) => exit_on_error(photo, combine::convert_files(photo, output, format)),
+            None => usage(),
+        },
+        [path] => extract::extract_mp4(path, false).unwrap(),
+        [flag, path] if flag == "--sidecar" => extract::extract_mp4(path, true).unwrap(),
+        _ => usage(),
+    }
+}
+
+fn exit_on_error(path: &str, result: std::io::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+fn usage() {
+    eprintln!("Usage: motionsplit [--sidecar] [<file or directory to extract>]");
+    eprintln!("       motionsplit combine <samsung|google> <still.jpg> <video.mp4> <output.jpg>");
+    eprintln!("       motionsplit convert <samsung|google> <motion photo.jpg> <output.jpg>");
+}
+
+fn parse_format(format: &str) -> Option<extract::MotionFormat> {
+    match format {
+        "samsung" => Some(extract::MotionFormat::Samsung),
+        "google" => Some(extract::MotionFormat::Google),
+        _ => None,
     }
 }
 
 fn open_ui() -> iced::Result {
     let mut settings = Settings::default();
-    settings.window.size = (500, 375);
+    settings.window.size = (760, 980);
     MotionSplit::run(settings)
 }
 
 enum Status {
-    Success,
-    Progress(String),
+    Success(report::Summary),
     Working,
+    Exported(PathBuf),
     Issue(String),
 }
 
 impl Display for Status {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Success => "Successfully extracted the motion pictures as mp4s",
-                Self::Working => "Starting conversion...",
-                Self::Issue(res) => res,
-                Self::Progress(res) => res,
-            }
-        )
+        match self {
+            Self::Success(summary) => write!(f, "Done: {}", summary),
+            Self::Working => write!(f, "Starting conversion..."),
+            Self::Exported(path) => write!(f, "Video written to {}", display_path(path)),
+            Self::Issue(res) => write!(f, "{}", res),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertTarget {
+    Keep,
+    Samsung,
+    Google,
+}
+
+impl ConvertTarget {
+    const ALL: [ConvertTarget; 3] = [Self::Keep, Self::Samsung, Self::Google];
+
+    fn format(self) -> Option<extract::MotionFormat> {
+        match self {
+            Self::Keep => None,
+            Self::Samsung => Some(extract::MotionFormat::Samsung),
+            Self::Google => Some(extract::MotionFormat::Google),
+        }
+    }
+}
+
+// Deriving it with `#[default]` would need Rust 1.62
+impl Default for ConvertTarget {
+    fn default() -> Self {
+        Self::Keep
+    }
+}
+
+impl Display for ConvertTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.format() {
+            Some(format) => write!(f, "{}", format),
+            None => write!(f, "Keep as is"),
+        }
+    }
+}
+
+/// The selected photo, when a single one is selected.
+struct PreviewPane {
+    image: Option<image::Handle>,
+    motion: Option<extract::Motion>,
+    export_button: button::State,
+}
+
+impl PreviewPane {
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let preview = preview::Preview::load(path)?;
+        Ok(Self {
+            image: preview.image.map(image::Handle::from_memory),
+            motion: preview.motion,
+            export_button: button::State::new(),
+        })
+    }
+
+    fn view(&mut self, enabled: bool) -> Element<'_, Message> {
+        let image: Element<Message> = match &self.image {
+            Some(handle) => Image::new(handle.clone())
+                .width(Length::Units(160))
+                .height(Length::Units(120))
+                .into(),
+            None => Text::new("No preview")
+                .size(14)
+                .width(Length::Units(160))
+                .into(),
+        };
+        let mut export = Button::new(&mut self.export_button, Text::new("Export video"));
+        if enabled && self.motion.is_some() {
+            export = export.on_press(Message::ExportVideo);
+        }
+        let motion = match &self.motion {
+            Some(motion) => format!("{}, starting at byte {}", motion, motion.offset),
+            None => String::from("No motion data"),
+        };
+        Row::new()
+            .spacing(10)
+            .align_items(Align::Center)
+            .push(image)
+            .push(
+                Column::new()
+                    .spacing(10)
+                    .push(Text::new(motion).size(14))
+                    .push(export),
+            )
+            .into()
+    }
+}
+
+/// A row of the review table, along with what the user made of it so far.
+struct ReviewRow {
+    row: review::Row,
+    include: bool,
+    /// Which of the identical copies is written
+    keep: usize,
+    date: String,
+    comparing: bool,
+    date_input: text_input::State,
+    compare_button: button::State,
+}
+
+impl ReviewRow {
+    fn kept(&self) -> &review::Candidate {
+        &self.row.candidates[self.keep]
+    }
+
+    /// Another copy brings its own date along, unless the date was edited.
+    fn keep(&mut self, keep: usize) {
+        if keep >= self.row.candidates.len() {
+            return;
+        }
+        if self.date.trim() == self.kept().date.as_deref().unwrap_or_default() {
+            self.date = self.row.candidates[keep].date.clone().unwrap_or_default();
+        }
+        self.keep = keep;
+    }
+
+    fn comparison<'a>(&self, idx: usize) -> Element<'a, Message> {
+        self.row
+            .candidates
+            .iter()
+            .enumerate()
+            .fold(
+                Row::new().spacing(10).padding(5),
+                |comparison, (candidate_idx, candidate)| {
+                    let date = match (&candidate.date, candidate.date_source) {
+                        (Some(date), Some(source)) => format!("{}, from {}", date, source),
+                        _ => String::from("No date"),
+                    };
+                    comparison.push(
+                        Column::new()
+                            .spacing(3)
+                            .width(Length::FillPortion(1))
+                            .push(
+                                Radio::new(candidate_idx, "Keep", Some(self.keep), move |keep| {
+                                    Message::ReviewKeep(idx, keep)
+                                })
+                                .size(14)
+                                .text_size(12),
+                            )
+                            .push(Text::new(display_path(&candidate.source)).size(12))
+                            .push(Text::new(progress::format_bytes(candidate.size as f64)).size(12))
+                            .push(Text::new(date).size(12))
+                            .push(
+                                Text::new(format!(
+                                    "Modified {}",
+                                    candidate
+                                        .modified
+                                        .as_deref()
+                                        .unwrap_or("at an unknown time")
+                                ))
+                                .size(12),
+                            ),
+                    )
+                },
+            )
+            .into()
+    }
+}
+
+/// The plan of a run, waiting to be confirmed.
+struct Review {
+    rows: Vec<ReviewRow>,
+    reply: review::Reply,
+    scroll: scrollable::State,
+    confirm_button: button::State,
+    cancel_button: button::State,
+}
+
+impl Review {
+    fn new(rows: Vec<review::Row>, reply: review::Reply) -> Self {
+        Self {
+            rows: rows
+                .into_iter()
+                .map(|row| ReviewRow {
+                    include: true,
+                    keep: 0,
+                    date: row.candidates[0].date.clone().unwrap_or_default(),
+                    comparing: false,
+                    date_input: text_input::State::new(),
+                    compare_button: button::State::new(),
+                    row,
+                })
+                .collect(),
+            reply,
+            scroll: scrollable::State::new(),
+            confirm_button: button::State::new(),
+            cancel_button: button::State::new(),
+        }
+    }
+
+    /// Dates that were left alone, or cleared, keep what was found.
+    fn decisions(&self) -> std::result::Result<Vec<review::Decision>, String> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let date = row.date.trim();
+                let date = if date.is_empty() || Some(date) == row.kept().date.as_deref() {
+                    None
+                } else {
+                    let date =
+                        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                            format!(
+                                "{} is not a date like 2021-12-31, for {}",
+                                date,
+                                display_path(&row.kept().source)
+                            )
+                        })?;
+                    Some(date)
+                };
+                Ok(review::Decision {
+                    include: row.include,
+                    keep: row.keep,
+                    date,
+                })
+            })
+            .collect()
+    }
+
+    fn view(&mut self, options: &file_task::Options) -> Element<'_, Message> {
+        let cell =
+            |text: String, portion| Text::new(text).size(12).width(Length::FillPortion(portion));
+        let header = Row::new()
+            .spacing(5)
+            .push(Space::new(Length::Units(20), Length::Shrink))
+            .push(cell("Source".into(), 4))
+            .push(cell("New name".into(), 4))
+            .push(Text::new("Date").size(12).width(Length::Units(80)))
+            .push(cell("Date from".into(), 2))
+            .push(cell("Notes".into(), 3));
+
+        let included = self.rows.iter().filter(|row| row.include).count();
+        let total = self.rows.len();
+        let table = self.rows.iter_mut().enumerate().fold(
+            Scrollable::new(&mut self.scroll)
+                .spacing(2)
+                .height(Length::Fill),
+            |table, (idx, row)| {
+                let date = row.date.trim();
+                let new_name = if !options.rename_files || date.is_empty() {
+                    String::from("Unchanged")
+                } else if chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+                    String::from("Invalid date")
+                } else {
+                    options
+                        .name_template
+                        .rename(date, &row.kept().source)
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                };
+                let kept = row.kept();
+                let source = display_path(&kept.source);
+                let date_source = match (&kept.date, kept.date_source) {
+                    (Some(found), _) if found != date => String::from("entered by hand"),
+                    (_, Some(source)) => source.to_string(),
+                    (_, None) if !date.is_empty() => String::from("entered by hand"),
+                    _ => String::new(),
+                };
+                let mut notes = Vec::new();
+                if let Some(format) = row.row.motion {
+                    notes.push(format!("{} motion photo", format));
+                }
+                if row.row.live_video {
+                    notes.push(String::from("Live Photo"));
+                }
+                let comparison = if row.comparing {
+                    Some(row.comparison(idx))
+                } else {
+                    None
+                };
+                let copies = row.row.candidates.len();
+                let mut line = Row::new()
+                    .spacing(5)
+                    .align_items(Align::Center)
+                    .push(Checkbox::new(row.include, "", move |include| {
+                        Message::ReviewInclude(idx, include)
+                    }))
+                    .push(cell(source, 4))
+                    .push(cell(new_name, 4))
+                    .push(
+                        TextInput::new(&mut row.date_input, "YYYY-MM-DD", &row.date, move |date| {
+                            Message::ReviewDateChanged(idx, date)
+                        })
+                        .size(12)
+                        .padding(2)
+                        .width(Length::Units(80)),
+                    )
+                    .push(cell(date_source, 2))
+                    .push(cell(notes.join(", "), 3));
+                if copies > 1 {
+                    line = line.push(
+                        Button::new(
+                            &mut row.compare_button,
+                            Text::new(format!("{} copies", copies)).size(12),
+                        )
+                        .on_press(Message::ReviewCompare(idx)),
+                    );
+                }
+                match comparison {
+                    Some(comparison) => table.push(line).push(comparison),
+                    None => table.push(line),
+                }
+            },
+        );
+
+        Column::new()
+            .padding(20)
+            .spacing(10)
+            .push(Text::new(format!(
+                "{} of {} files will be written",
+                included, total
+            )))
+            .push(header)
+            .push(Rule::horizontal(0))
+            .push(table)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .push(Space::new(Length::Fill, Length::Shrink))
+                    .push(
+                        Button::new(&mut self.cancel_button, Text::new("Cancel"))
+                            .on_press(Message::CancelReview),
+                    )
+                    .push(
+                        Button::new(&mut self.confirm_button, Text::new("Write files"))
+                            .on_press(Message::ConfirmReview),
+                    ),
+            )
+            .into()
     }
 }
 
 #[derive(Default)]
 struct MotionSplit {
-    path: Option<PathBuf>,
+    /// The files and folders to convert, more than one when several were dropped at once
+    sources: Vec<PathBuf>,
     output_path: Option<PathBuf>,
     status: Option<Status>,
+    progress: Option<progress::Tracker>,
     filter_duplicates: bool,
     rename_files: bool,
     extract_mp4: bool,
+    motion_sidecar: bool,
+    other_media: bool,
+    set_file_times: bool,
+    write_exif_date: bool,
+    write_sidecars: bool,
+    convert_to: ConvertTarget,
+    name_template: String,
+    video_naming: naming::VideoNaming,
+    video_folder: String,
+    include_filter: String,
+    exclude_filter: String,
+    date_from: String,
+    date_until: String,
+    camera_filter: String,
+    min_resolution: String,
+    motion_only: bool,
+    gps_only: bool,
+    time_shifts: String,
+    shift_exif: bool,
     converting: bool,
+    review: Option<Review>,
+    preview: Option<PreviewPane>,
+    /// Set while files are dragged over the window, so the drop replaces the current selection
+    hovering: bool,
+    errors: Vec<error::TaskError>,
     pick_file_button: button::State,
     pick_directory_button: button::State,
     pick_destination_button: button::State,
     convert_button: button::State,
+    export_errors_button: button::State,
+    errors_scroll: scrollable::State,
     path_display: text_input::State,
     output_path_display: text_input::State,
+    include_filter_input: text_input::State,
+    exclude_filter_input: text_input::State,
+    date_from_input: text_input::State,
+    date_until_input: text_input::State,
+    camera_filter_input: text_input::State,
+    min_resolution_input: text_input::State,
+    time_shifts_input: text_input::State,
+    convert_to_list: pick_list::State<ConvertTarget>,
+    name_template_input: text_input::State,
+    video_naming_list: pick_list::State<naming::VideoNaming>,
+    video_folder_input: text_input::State,
 }
 
 #[derive(Debug, Clone)]
@@ -74,14 +491,57 @@ pub enum Message {
     ToggleDuplicate(bool),
     ToggleRename(bool),
     ToggleMotionExtract(bool),
+    ToggleMotionSidecar(bool),
+    ToggleOtherMedia(bool),
+    ToggleFileTimes(bool),
+    ToggleExifDate(bool),
+    ToggleSidecars(bool),
+    SelectConvertTarget(ConvertTarget),
+    NameTemplateChanged(String),
+    SelectVideoNaming(naming::VideoNaming),
+    VideoFolderChanged(String),
+    IncludeFilterChanged(String),
+    ExcludeFilterChanged(String),
+    DateFromChanged(String),
+    DateUntilChanged(String),
+    CameraFilterChanged(String),
+    MinResolutionChanged(String),
+    ToggleMotionOnly(bool),
+    ToggleGpsOnly(bool),
+    TimeShiftsChanged(String),
+    ToggleShiftExif(bool),
+    ReviewInclude(usize, bool),
+    ReviewDateChanged(usize, String),
+    ReviewCompare(usize),
+    ReviewKeep(usize, usize),
+    ConfirmReview,
+    CancelReview,
     Convert,
+    ExportErrors,
+    ExportVideo,
+    FilesHovered,
+    FilesHoveredLeft,
+    FileDropped(PathBuf),
     TaskUpdate(file_task::Update),
     Noop,
 }
 
+fn sources_to_string(sources: &[PathBuf]) -> String {
+    match sources {
+        [] => path_to_str(None).to_string(),
+        [source] => path_to_str(Some(source)).to_string(),
+        _ => {
+            let sources: Vec<&str> = sources
+                .iter()
+                .map(|source| path_to_str(Some(source)))
+                .collect();
+            format!("{} items: {}", sources.len(), sources.join(", "))
+        }
+    }
+}
+
 fn path_to_str(path: Option<&PathBuf>) -> &str {
-    path.map(|p| p.to_str())
-        .flatten()
+    path.and_then(|p| p.to_str())
         .map(|s| {
             if cfg!(windows) {
                 s.trim_start_matches(r"\\?\")
@@ -92,6 +552,104 @@ fn path_to_str(path: Option<&PathBuf>) -> &str {
         .unwrap_or("None")
 }
 
+pub fn display_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        path.trim_start_matches(r"\\?\").to_string()
+    } else {
+        path.into_owned()
+    }
+}
+
+/// Lets the user pick where the video of the photo at `path` goes, returning where it went.
+fn export_video(path: &Path, sidecar: bool) -> std::result::Result<Option<PathBuf>, String> {
+    let name = extract::motion_path(path);
+    let name = name.file_name().unwrap_or_default().to_string_lossy();
+    let destination = native_dialog::FileDialog::default()
+        .set_filename(&name)
+        .add_filter("MP4 video", &["mp4"])
+        .show_save_single_file()
+        .map_err(|e| e.to_string())?;
+    let destination = match destination {
+        Some(destination) => destination,
+        None => return Ok(None),
+    };
+    match extract::extract_file(path, &destination, sidecar, None) {
+        Ok(Some(_)) => Ok(Some(destination)),
+        Ok(None) => Err(String::from("The photo has no motion data")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn export_errors(errors: &[error::TaskError]) -> std::result::Result<(), String> {
+    let path = native_dialog::FileDialog::default()
+        .add_filter("Text file", &["txt"])
+        .show_save_single_file()
+        .map_err(|e| e.to_string())?;
+    if let Some(path) = path {
+        let contents: String = errors.iter().map(|e| format!("{}\n", e)).collect();
+        std::fs::write(path, contents).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+impl MotionSplit {
+    /// A single file is previewed, and a destination of the wrong kind is forgotten.
+    fn set_sources(&mut self, sources: Vec<PathBuf>) {
+        self.preview = None;
+        let single_file = match sources.as_slice() {
+            [source] if source.is_file() => Some(source),
+            _ => None,
+        };
+        if let Some(path) = single_file {
+            match PreviewPane::load(path) {
+                Ok(preview) => self.preview = Some(preview),
+                Err(e) => {
+                    self.status = Some(Status::Issue(format!("Unable to preview the photo: {}", e)))
+                }
+            }
+        }
+        if matches!(&self.output_path, Some(output) if output.is_dir() == single_file.is_some()) {
+            self.output_path = None;
+        }
+        self.sources = sources;
+    }
+
+    fn photo_filter(&self) -> std::result::Result<filter::PhotoFilter, String> {
+        filter::PhotoFilter::parse(
+            &self.date_from,
+            &self.date_until,
+            &self.camera_filter,
+            &self.min_resolution,
+            self.motion_only,
+            self.gps_only,
+        )
+    }
+
+    fn options(&self) -> file_task::Options {
+        file_task::Options {
+            filter_duplicates: self.filter_duplicates,
+            rename_files: self.rename_files,
+            extract_mp4: self.extract_mp4,
+            motion_sidecar: self.motion_sidecar,
+            other_media: self.other_media,
+            set_file_times: self.set_file_times,
+            write_exif_date: self.write_exif_date,
+            write_sidecars: self.write_sidecars,
+            // Only the renamed copies are converted
+            convert_to: self.convert_to.format().filter(|_| self.rename_files),
+            name_template: naming::NameTemplate::parse(&self.name_template),
+            video_naming: self.video_naming,
+            video_folder: self.video_folder.trim().to_string(),
+            filter: filter::FileFilter::parse(&self.include_filter, &self.exclude_filter),
+            // Checked before the run starts
+            photo_filter: self.photo_filter().unwrap_or_default(),
+            time_shifts: shift::TimeShifts::parse(&self.time_shifts).unwrap_or_default(),
+            shift_exif: self.shift_exif,
+        }
+    }
+}
+
 impl Application for MotionSplit {
     type Message = Message;
     type Executor = executor::Default;
@@ -106,47 +664,106 @@ impl Application for MotionSplit {
     }
 
     fn update(&mut self, message: Message, _clipboard: &mut Clipboard) -> Command<Self::Message> {
-        if let Message::ToggleDuplicate(state) = message {
-            self.filter_duplicates = state;
+        if let Message::ReviewInclude(idx, include) = message {
+            if let Some(row) = self
+                .review
+                .as_mut()
+                .and_then(|review| review.rows.get_mut(idx))
+            {
+                row.include = include;
+            }
             return Command::none();
         }
-        if let Message::ToggleRename(state) = message {
-            self.rename_files = state;
+        if let Message::ReviewDateChanged(idx, date) = message {
+            if let Some(row) = self
+                .review
+                .as_mut()
+                .and_then(|review| review.rows.get_mut(idx))
+            {
+                row.date = date;
+            }
             return Command::none();
         }
-        if let Message::ToggleMotionExtract(state) = message {
-            self.extract_mp4 = state;
+        if let Message::ReviewCompare(idx) = message {
+            if let Some(row) = self
+                .review
+                .as_mut()
+                .and_then(|review| review.rows.get_mut(idx))
+            {
+                row.comparing = !row.comparing;
+            }
+            return Command::none();
+        }
+        if let Message::ReviewKeep(idx, keep) = message {
+            if let Some(row) = self
+                .review
+                .as_mut()
+                .and_then(|review| review.rows.get_mut(idx))
+            {
+                row.keep(keep);
+            }
+            return Command::none();
+        }
+        if let Message::ConfirmReview = message {
+            if let Some(review) = self.review.take() {
+                match review.decisions() {
+                    Ok(decisions) => {
+                        // The task stops by itself if it's no longer there to listen
+                        let _ = review.reply.send(Some(decisions));
+                        self.status = Some(Status::Working);
+                    }
+                    Err(e) => {
+                        self.status = Some(Status::Issue(e));
+                        self.review = Some(review);
+                    }
+                }
+            }
+            return Command::none();
+        }
+        if let Message::CancelReview = message {
+            if let Some(review) = self.review.take() {
+                let _ = review.reply.send(None);
+                self.converting = false;
+                self.status = Some(Status::Issue("Cancelled, nothing was written".into()));
+            }
             return Command::none();
         }
         if let Message::TaskUpdate(update) = message {
             match update {
-                file_task::Update::Progress { path, done, total } => {
-                    if done == total {
-                        self.converting = false;
-                        self.status = Some(Status::Success);
-                    } else {
-                        let mut path_message = path.to_string_lossy().into_owned();
-                        if cfg!(windows) {
-                            path_message = path_message.trim_start_matches(r"\\?\").to_string();
-                        }
-                        self.status = Some(Status::Progress(format!(
-                            "{}: {}/{}",
-                            path_message, done, total
-                        )));
-                    }
+                file_task::Update::Progress(progress) => match self.progress.as_mut() {
+                    Some(tracker) => tracker.update(progress),
+                    None => self.progress = Some(progress::Tracker::new(progress)),
+                },
+                file_task::Update::Error(e) => self.errors.push(e),
+                file_task::Update::Review { rows, reply } => {
+                    self.progress = None;
+                    self.review = Some(Review::new(rows, reply));
+                }
+                file_task::Update::Finished { errors, summary } => {
+                    self.converting = false;
+                    self.progress = None;
+                    self.status = Some(Status::Success(summary));
+                    self.errors = errors;
                 }
-                file_task::Update::Error(s) => self.status = Some(Status::Issue(s)),
             }
 
             return Command::none();
         }
         if let Message::Convert = message {
-            match (self.path.as_ref(), self.output_path.as_ref()) {
-                (Some(_), Some(_)) => {
+            match (self.sources.is_empty(), self.output_path.as_ref()) {
+                (false, Some(_)) => {
+                    if let Err(e) = self
+                        .photo_filter()
+                        .and(shift::TimeShifts::parse(&self.time_shifts))
+                    {
+                        self.status = Some(Status::Issue(e));
+                        return Command::none();
+                    }
                     self.status = Some(Status::Working);
                     self.converting = true;
+                    self.errors.clear();
                 }
-                (None, _) => {
+                (true, _) => {
                     self.status = Some(Status::Issue(
                         "Please select a file or directory to convert".into(),
                     ))
@@ -159,21 +776,158 @@ impl Application for MotionSplit {
             }
             return Command::none();
         }
+        if let Message::ExportErrors = message {
+            if let Err(e) = export_errors(&self.errors) {
+                self.status = Some(Status::Issue(format!("Unable to export the errors: {}", e)));
+            }
+            return Command::none();
+        }
 
+        // The settings of a run stay as they are while it's going
         if self.converting {
             return Command::none();
         }
 
-        // The destination should have the same pathbuf type (file/dir) as the path
-        let to_match = if let Message::SelectDestination = message {
-            if let Some(path) = self.path.as_ref() {
-                if path.is_dir() {
-                    Message::SelectDirectory
-                } else {
-                    Message::SelectFile
-                }
+        if let Message::ToggleDuplicate(state) = message {
+            self.filter_duplicates = state;
+            return Command::none();
+        }
+        if let Message::ToggleRename(state) = message {
+            self.rename_files = state;
+            return Command::none();
+        }
+        if let Message::ToggleMotionExtract(state) = message {
+            self.extract_mp4 = state;
+            return Command::none();
+        }
+        if let Message::ToggleMotionSidecar(state) = message {
+            self.motion_sidecar = state;
+            return Command::none();
+        }
+        if let Message::ToggleOtherMedia(state) = message {
+            self.other_media = state;
+            return Command::none();
+        }
+        if let Message::ToggleFileTimes(state) = message {
+            self.set_file_times = state;
+            return Command::none();
+        }
+        if let Message::ToggleExifDate(state) = message {
+            self.write_exif_date = state;
+            return Command::none();
+        }
+        if let Message::ToggleSidecars(state) = message {
+            self.write_sidecars = state;
+            return Command::none();
+        }
+        if let Message::SelectConvertTarget(target) = message {
+            self.convert_to = target;
+            return Command::none();
+        }
+        if let Message::NameTemplateChanged(template) = message {
+            self.name_template = template;
+            return Command::none();
+        }
+        if let Message::SelectVideoNaming(naming) = message {
+            self.video_naming = naming;
+            return Command::none();
+        }
+        if let Message::VideoFolderChanged(folder) = message {
+            self.video_folder = folder;
+            return Command::none();
+        }
+        if let Message::IncludeFilterChanged(filter) = message {
+            self.include_filter = filter;
+            return Command::none();
+        }
+        if let Message::ExcludeFilterChanged(filter) = message {
+            self.exclude_filter = filter;
+            return Command::none();
+        }
+        if let Message::DateFromChanged(date) = message {
+            self.date_from = date;
+            return Command::none();
+        }
+        if let Message::DateUntilChanged(date) = message {
+            self.date_until = date;
+            return Command::none();
+        }
+        if let Message::CameraFilterChanged(camera) = message {
+            self.camera_filter = camera;
+            return Command::none();
+        }
+        if let Message::MinResolutionChanged(resolution) = message {
+            self.min_resolution = resolution;
+            return Command::none();
+        }
+        if let Message::ToggleMotionOnly(state) = message {
+            self.motion_only = state;
+            return Command::none();
+        }
+        if let Message::ToggleGpsOnly(state) = message {
+            self.gps_only = state;
+            return Command::none();
+        }
+        if let Message::TimeShiftsChanged(shifts) = message {
+            self.time_shifts = shifts;
+            return Command::none();
+        }
+        if let Message::ToggleShiftExif(state) = message {
+            self.shift_exif = state;
+            return Command::none();
+        }
+        if let Message::FilesHovered = message {
+            self.hovering = true;
+            return Command::none();
+        }
+        if let Message::FilesHoveredLeft = message {
+            self.hovering = false;
+            return Command::none();
+        }
+        if let Message::FileDropped(path) = message {
+            // Every dropped item comes as an event of its own, the first of a drop starts over
+            let mut sources = if self.hovering {
+                Vec::new()
             } else {
-                Message::Noop
+                self.sources.clone()
+            };
+            self.hovering = false;
+            match canonicalize(&path) {
+                Ok(path) if !sources.contains(&path) => sources.push(path),
+                Ok(_) => {}
+                Err(e) => {
+                    self.status = Some(Status::Issue(format!(
+                        "Unable to open {}: {}",
+                        display_path(&path),
+                        e
+                    )))
+                }
+            }
+            self.set_sources(sources);
+            return Command::none();
+        }
+
+        if let Message::ExportVideo = message {
+            if let [path] = self.sources.as_slice() {
+                match export_video(path, self.motion_sidecar) {
+                    Ok(Some(destination)) => self.status = Some(Status::Exported(destination)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.status =
+                            Some(Status::Issue(format!("Unable to export the video: {}", e)))
+                    }
+                }
+            }
+            return Command::none();
+        }
+
+        // The destination should have the same pathbuf type (file/dir) as the path, several
+        // inputs go into a directory
+        let to_match = if let Message::SelectDestination = message {
+            match self.sources.as_slice() {
+                [] => Message::Noop,
+                [path] if !path.is_dir() => Message::SelectFile,
+                _ => Message::SelectDirectory,
             }
         } else {
             message.clone()
@@ -198,39 +952,59 @@ impl Application for MotionSplit {
             _ => return Command::none(),
         };
 
-        let opt = path
-            .map(|buf| match canonicalize(buf) {
-                Err(e) => {
-                    dbg!(e);
-                    None
-                }
-                Ok(x) => Some(x),
-            })
-            .flatten();
+        let opt = path.and_then(|buf| match canonicalize(&buf) {
+            Err(e) => {
+                self.status = Some(Status::Issue(format!(
+                    "Unable to open {}: {}",
+                    display_path(&buf),
+                    e
+                )));
+                None
+            }
+            Ok(x) => Some(x),
+        });
         match message {
             Message::SelectDestination => self.output_path = opt,
-            Message::SelectFile | Message::SelectDirectory => self.path = opt,
+            Message::SelectFile | Message::SelectDirectory => {
+                self.set_sources(opt.into_iter().collect());
+            }
             _ => {}
         }
         Command::none()
     }
 
     fn subscription(&self) -> iced::Subscription<Self::Message> {
-        if self.converting {
+        let drops = iced_native::subscription::events_with(|event, _status| match event {
+            iced_native::Event::Window(iced_native::window::Event::FileHovered(_)) => {
+                Some(Message::FilesHovered)
+            }
+            iced_native::Event::Window(iced_native::window::Event::FilesHoveredLeft) => {
+                Some(Message::FilesHoveredLeft)
+            }
+            iced_native::Event::Window(iced_native::window::Event::FileDropped(path)) => {
+                Some(Message::FileDropped(path))
+            }
+            _ => None,
+        });
+        let task = if self.converting {
             Subscription::from_recipe(file_task::FileTask::new(
-                self.path.as_ref().unwrap().clone(),
+                self.sources.clone(),
                 self.output_path.as_ref().unwrap().clone(),
-                self.filter_duplicates,
-                self.rename_files,
-                self.extract_mp4,
+                self.options(),
             ))
         } else {
             Subscription::none()
-        }
+        };
+        Subscription::batch(vec![drops, task])
     }
 
-    fn view(&mut self) -> Element<Message> {
-        let path_message = path_to_str(self.path.as_ref());
+    fn view(&mut self) -> Element<'_, Message> {
+        let options = self.options();
+        if let Some(review) = self.review.as_mut() {
+            return review.view(&options);
+        }
+
+        let path_message = sources_to_string(&self.sources);
         let output_path_message = path_to_str(self.output_path.as_ref());
 
         let mut pick_file = Button::new(&mut self.pick_file_button, Text::new("Select file"));
@@ -247,7 +1021,7 @@ impl Application for MotionSplit {
         if !self.converting {
             pick_file = pick_file.on_press(Message::SelectFile);
             pick_directory = pick_directory.on_press(Message::SelectDirectory);
-            if self.path.is_some() {
+            if !self.sources.is_empty() {
                 pick_destination = pick_destination.on_press(Message::SelectDestination);
                 if self.output_path.is_some() {
                     convert = convert.on_press(Message::Convert);
@@ -256,7 +1030,31 @@ impl Application for MotionSplit {
         }
         self.path_display.unfocus();
 
-        Column::new()
+        let status: Element<Message> = match self.progress.as_ref() {
+            Some(tracker) => Column::new()
+                .padding(10)
+                .spacing(5)
+                .push(ProgressBar::new(0.0..=1.0, tracker.fraction()).height(Length::Units(20)))
+                .push(Text::new(tracker.to_string()).size(14))
+                .push(Text::new(display_path(&tracker.progress().path)).size(14))
+                .into(),
+            None => Row::new()
+                .padding(10)
+                .align_items(Align::Start)
+                .push(Text::new("Status: "))
+                .push(
+                    Text::new(
+                        self.status
+                            .as_ref()
+                            .map(|s| s.to_string())
+                            .unwrap_or_default(),
+                    )
+                    .width(Length::Fill),
+                )
+                .into(),
+        };
+
+        let content = Column::new()
             .push(
                 Column::new()
                     .width(iced::Length::Fill)
@@ -265,9 +1063,12 @@ impl Application for MotionSplit {
                     .spacing(5)
                     .align_items(Align::Start)
                     .push(
-                        TextInput::new(&mut self.path_display, path_message, path_message, |_| {
-                            Message::Noop
-                        })
+                        TextInput::new(
+                            &mut self.path_display,
+                            &path_message,
+                            &path_message,
+                            |_| Message::Noop,
+                        )
                         .padding(3),
                     )
                     .push(
@@ -276,8 +1077,13 @@ impl Application for MotionSplit {
                             .align_items(Align::Center)
                             .push(pick_file)
                             .push(pick_directory)
+                            .push(Text::new("or drop files and folders here").size(14))
                             .push(Space::new(Length::Fill, Length::Shrink)),
                     )
+                    .push(match self.preview.as_mut() {
+                        Some(preview) => preview.view(!self.converting),
+                        None => Space::new(Length::Shrink, Length::Shrink).into(),
+                    })
                     .push(
                         TextInput::new(
                             &mut self.output_path_display,
@@ -300,8 +1106,202 @@ impl Application for MotionSplit {
                     ))
                     .push(Checkbox::new(
                         self.extract_mp4,
-                        "Extract Samsung motion pictures",
+                        "Extract motion pictures",
                         Message::ToggleMotionExtract,
+                    ))
+                    .push(Checkbox::new(
+                        self.motion_sidecar,
+                        "Save still frame info with extracted videos",
+                        Message::ToggleMotionSidecar,
+                    ))
+                    .push(Checkbox::new(
+                        self.other_media,
+                        "Include videos and other media",
+                        Message::ToggleOtherMedia,
+                    ))
+                    .push(Checkbox::new(
+                        self.set_file_times,
+                        "Set file dates to when the photo was taken",
+                        Message::ToggleFileTimes,
+                    ))
+                    .push(Checkbox::new(
+                        self.write_exif_date,
+                        "Write missing dates into the EXIF of copies",
+                        Message::ToggleExifDate,
+                    ))
+                    .push(Checkbox::new(
+                        self.write_sidecars,
+                        "Write XMP and JSON sidecars next to renamed copies",
+                        Message::ToggleSidecars,
+                    ))
+                    .push(if self.rename_files {
+                        let row = Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Motion photo format:"))
+                            .push(PickList::new(
+                                &mut self.convert_to_list,
+                                &ConvertTarget::ALL[..],
+                                Some(self.convert_to),
+                                Message::SelectConvertTarget,
+                            ));
+                        if self.convert_to == ConvertTarget::Google {
+                            row.push(Text::new("Drops Samsung's other trailer data").size(14))
+                        } else {
+                            row
+                        }
+                    } else {
+                        Row::new().push(Text::new(
+                            "Motion photos are only converted when files are renamed",
+                        ))
+                    })
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Names:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.name_template_input,
+                                    naming::NameTemplate::DEFAULT,
+                                    &self.name_template,
+                                    Message::NameTemplateChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Videos:").width(Length::Units(70)))
+                            .push(PickList::new(
+                                &mut self.video_naming_list,
+                                &naming::VideoNaming::ALL[..],
+                                Some(self.video_naming),
+                                Message::SelectVideoNaming,
+                            ))
+                            .push(
+                                TextInput::new(
+                                    &mut self.video_folder_input,
+                                    "Next to the photo, e.g. videos",
+                                    &self.video_folder,
+                                    Message::VideoFolderChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Include:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.include_filter_input,
+                                    "All photos, e.g. jpg, heic, IMG_*",
+                                    &self.include_filter,
+                                    Message::IncludeFilterChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Exclude:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.exclude_filter_input,
+                                    "Nothing, e.g. **/.thumbnails/**, *-edited.jpg",
+                                    &self.exclude_filter,
+                                    Message::ExcludeFilterChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Taken:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.date_from_input,
+                                    "From, e.g. 2021-01-01",
+                                    &self.date_from,
+                                    Message::DateFromChanged,
+                                )
+                                .padding(3),
+                            )
+                            .push(
+                                TextInput::new(
+                                    &mut self.date_until_input,
+                                    "Until, e.g. 2021-12-31",
+                                    &self.date_until,
+                                    Message::DateUntilChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Camera:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.camera_filter_input,
+                                    "Any, e.g. Pixel 6",
+                                    &self.camera_filter,
+                                    Message::CameraFilterChanged,
+                                )
+                                .padding(3),
+                            )
+                            .push(
+                                TextInput::new(
+                                    &mut self.min_resolution_input,
+                                    "Any size, e.g. 1920x1080",
+                                    &self.min_resolution,
+                                    Message::MinResolutionChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(20)
+                            .push(Checkbox::new(
+                                self.motion_only,
+                                "Only motion photos",
+                                Message::ToggleMotionOnly,
+                            ))
+                            .push(Checkbox::new(
+                                self.gps_only,
+                                "Only photos with a location",
+                                Message::ToggleGpsOnly,
+                            )),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .align_items(Align::Center)
+                            .push(Text::new("Shift:").width(Length::Units(70)))
+                            .push(
+                                TextInput::new(
+                                    &mut self.time_shifts_input,
+                                    "None, e.g. -1y +1h or camera Canon EOS: -1y +1h",
+                                    &self.time_shifts,
+                                    Message::TimeShiftsChanged,
+                                )
+                                .padding(3),
+                            ),
+                    )
+                    .push(Checkbox::new(
+                        self.shift_exif,
+                        "Write shifted dates into the EXIF of copies",
+                        Message::ToggleShiftExif,
                     )),
             )
             .push(
@@ -315,21 +1315,32 @@ impl Application for MotionSplit {
                             .push(convert),
                     )
                     .push(Rule::horizontal(0))
-                    .push(
-                        Row::new()
-                            .padding(10)
-                            .align_items(Align::Start)
-                            .push(Text::new("Status: "))
-                            .push(
-                                Text::new(
-                                    self.status
-                                        .as_ref()
-                                        .map(|s| s.to_string())
-                                        .unwrap_or_else(|| "".to_string()),
-                                )
-                                .width(Length::Fill),
-                            ),
-                    ),
+                    .push(status),
+            );
+
+        if self.errors.is_empty() {
+            return content.into();
+        }
+
+        let errors = self.errors.iter().fold(
+            Scrollable::new(&mut self.errors_scroll)
+                .width(Length::Fill)
+                .height(Length::Units(80))
+                .spacing(2),
+            |list, error| list.push(Text::new(error.to_string()).size(14)),
+        );
+        let mut export = Button::new(&mut self.export_errors_button, Text::new("Export errors"));
+        if !self.converting {
+            export = export.on_press(Message::ExportErrors);
+        }
+
+        content
+            .push(
+                Column::new()
+                    .padding(10)
+                    .spacing(5)
+                    .push(errors)
+                    .push(export),
             )
             .into()
     }
@@ -0,0 +1,449 @@
+use std::convert::TryFrom;
+use std::io;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use exif::{Exif, In, Tag, Value};
+
+use crate::date::{exif_ascii, LocalDate};
+use crate::isobmff::{self, BmffBox, ByteReader};
+
+/// QuickTime's language code for "undetermined", used for the location string.
+const UNDETERMINED_LANGUAGE: u16 = 0x55C4;
+
+/// MP4 and QuickTime timestamps count the seconds since 1904 rather than 1970.
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd(1904, 1, 1).and_hms(0, 0, 0)
+}
+
+/// The creation time from the movie header, or `None` if the encoder left it unset.
+pub fn creation_time(buf: &[u8]) -> Option<NaiveDateTime> {
+    let moov = isobmff::find_box(buf, 0..buf.len(), b"moov")?;
+    let mvhd = isobmff::find_box(buf, moov.payload(), b"mvhd")?;
+    let mut reader = ByteReader::new(&buf[..mvhd.end], mvhd.payload().start);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    let seconds = if version == 1 {
+        reader.u64()?
+    } else {
+        reader.u32()? as u64
+    };
+    if seconds == 0 || seconds > u32::MAX as u64 * 4 {
+        return None;
+    }
+    epoch().checked_add_signed(Duration::seconds(seconds as i64))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+impl Location {
+    /// The ISO 6709 notation QuickTime stores locations in, like `+52.3676+004.9041/`.
+    fn iso6709(&self) -> String {
+        let mut location = format!("{:+08.4}{:+09.4}", self.latitude, self.longitude);
+        if let Some(altitude) = self.altitude {
+            location.push_str(&format!("{:+.3}", altitude));
+        }
+        location.push('/');
+        location
+    }
+}
+
+/// When and where a photo was taken, to be copied into the video that belongs to it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metadata {
+    /// In UTC, like the timestamps of the movie header
+    pub date: Option<NaiveDateTime>,
+    pub location: Option<Location>,
+}
+
+impl Metadata {
+    pub fn from_exif(exif: &Exif) -> Self {
+        Self {
+            date: LocalDate::from_exif(exif).map(|date| date.utc()),
+            location: exif_location(exif),
+        }
+    }
+}
+
+fn exif_location(exif: &Exif) -> Option<Location> {
+    let coordinate = |tag: Tag, reference: Tag, negative: &[u8]| -> Option<f64> {
+        let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(parts) if parts.len() == 3 => {
+                parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
+            }
+            _ => return None,
+        };
+        let sign = if exif_ascii(exif, reference)? == negative {
+            -1.0
+        } else {
+            1.0
+        };
+        Some(sign * degrees).filter(|degrees| degrees.is_finite())
+    };
+    let altitude = match exif
+        .get_field(Tag::GPSAltitude, In::PRIMARY)
+        .map(|f| &f.value)
+    {
+        Some(Value::Rational(altitude)) if !altitude.is_empty() => {
+            let below_sea_level = matches!(
+                exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).map(|f| &f.value),
+                Some(Value::Byte(reference)) if reference.first() == Some(&1)
+            );
+            let altitude = altitude[0].to_f64();
+            Some(if below_sea_level { -altitude } else { altitude }).filter(|a| a.is_finite())
+        }
+        _ => None,
+    };
+    Some(Location {
+        latitude: coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S")?,
+        longitude: coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W")?,
+        altitude,
+    })
+}
+
+/// Writes `metadata` into the movie: the date into the creation and modification times of the
+/// movie header, and the location as a `©xyz` atom in its user data. A location the movie
+/// already has is kept. Returns `None` if the movie can't be parsed.
+pub fn with_metadata(buf: &[u8], metadata: &Metadata) -> Option<Vec<u8>> {
+    let moov = isobmff::find_box(buf, 0..buf.len(), b"moov")?;
+    let mut movie = buf.to_vec();
+
+    if let Some(date) = metadata.date {
+        let mvhd = isobmff::find_box(buf, moov.payload(), b"mvhd")?;
+        let seconds = u64::try_from(date.signed_duration_since(epoch()).num_seconds()).ok()?;
+        let times = mvhd.payload().start + 4;
+        if buf.get(mvhd.payload().start)? == &1 {
+            movie.get_mut(times..times + 16)?[..8].copy_from_slice(&seconds.to_be_bytes());
+            movie[times + 8..times + 16].copy_from_slice(&seconds.to_be_bytes());
+        } else {
+            let seconds = u32::try_from(seconds).ok()?.to_be_bytes();
+            movie.get_mut(times..times + 8)?[..4].copy_from_slice(&seconds);
+            movie[times + 4..times + 8].copy_from_slice(&seconds);
+        }
+    }
+
+    let location = match metadata.location {
+        Some(location) => location,
+        None => return Some(movie),
+    };
+    let udta = isobmff::find_box(buf, moov.payload(), b"udta");
+    let has_location = matches!(
+        udta,
+        Some(udta) if isobmff::children(buf, &udta).any(|child| child.is(b"\xA9xyz"))
+    );
+    if has_location {
+        return Some(movie);
+    }
+
+    let text = location.iso6709();
+    let mut inserted = Vec::with_capacity(text.len() + 20);
+    if udta.is_none() {
+        inserted.extend_from_slice(&(text.len() as u32 + 20).to_be_bytes());
+        inserted.extend_from_slice(b"udta");
+    }
+    inserted.extend_from_slice(&(text.len() as u32 + 12).to_be_bytes());
+    inserted.extend_from_slice(b"\xA9xyz");
+    inserted.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    inserted.extend_from_slice(&UNDETERMINED_LANGUAGE.to_be_bytes());
+    inserted.extend_from_slice(text.as_bytes());
+
+    let at = udta.map_or(moov.end, |udta| udta.end);
+    for parent in std::iter::once(moov).chain(udta) {
+        grow(&mut movie, &parent, inserted.len())?;
+    }
+    // When the movie header comes before the media data, growing it moves the samples along
+    shift_chunk_offsets(buf, &mut movie, &moov, at, inserted.len())?;
+    movie.splice(at..at, inserted);
+    Some(movie)
+}
+
+fn grow(movie: &mut [u8], parent: &BmffBox, by: usize) -> Option<()> {
+    let size = parent.end - parent.start + by;
+    if parent.header_len == 16 {
+        movie[parent.start + 8..parent.start + 16].copy_from_slice(&(size as u64).to_be_bytes());
+    } else {
+        movie[parent.start..parent.start + 4]
+            .copy_from_slice(&u32::try_from(size).ok()?.to_be_bytes());
+    }
+    Some(())
+}
+
+/// A chunk offset in one of the sample tables, along with where and how wide it's stored.
+struct ChunkOffset {
+    pos: usize,
+    len: usize,
+    offset: u64,
+}
+
+/// Collects the chunk offsets of every track within `parent` into `found`.
+fn chunk_offsets(buf: &[u8], parent: &BmffBox, found: &mut Vec<ChunkOffset>) -> Option<()> {
+    for child in isobmff::children(buf, parent) {
+        if [b"trak", b"mdia", b"minf", b"stbl"].contains(&&child.kind) {
+            chunk_offsets(buf, &child, found)?;
+        } else if child.is(b"stco") || child.is(b"co64") {
+            let len = if child.is(b"co64") { 8 } else { 4 };
+            let mut reader = ByteReader::new(&buf[..child.end], child.payload().start + 4);
+            let count = reader.u32()?;
+            for _ in 0..count {
+                let pos = reader.position();
+                let offset = reader.uint(len)?;
+                found.push(ChunkOffset { pos, len, offset });
+            }
+        }
+    }
+    Some(())
+}
+
+/// Moves every chunk offset that points past `at` along by `by` bytes.
+fn shift_chunk_offsets(
+    buf: &[u8],
+    movie: &mut [u8],
+    moov: &BmffBox,
+    at: usize,
+    by: usize,
+) -> Option<()> {
+    let mut offsets = Vec::new();
+    chunk_offsets(buf, moov, &mut offsets)?;
+    for chunk in offsets.iter().filter(|chunk| chunk.offset >= at as u64) {
+        let shifted = chunk.offset + by as u64;
+        let pos = chunk.pos;
+        if chunk.len == 8 {
+            movie[pos..pos + 8].copy_from_slice(&shifted.to_be_bytes());
+        } else {
+            movie[pos..pos + 4].copy_from_slice(&u32::try_from(shifted).ok()?.to_be_bytes());
+        }
+    }
+    Some(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Checks that `buf` holds a complete movie: a `ftyp` box followed by at least a `moov` and an
+/// `mdat` box that fit, with every chunk of samples inside of it. Returns the length of the
+/// movie, without any trailing bytes that aren't part of it.
+pub fn validate(buf: &[u8]) -> io::Result<usize> {
+    let mut boxes = isobmff::boxes(buf, 0..buf.len());
+    let top: Vec<BmffBox> = boxes.by_ref().collect();
+    let end = boxes.position();
+    if !matches!(top.first(), Some(ftyp) if ftyp.is(b"ftyp")) {
+        return Err(invalid("The video doesn't start with a ftyp box"));
+    }
+
+    let moov = top.iter().find(|found| found.is(b"moov"));
+    let mdat = top.iter().find(|found| found.is(b"mdat"));
+    let moov = match (moov, mdat) {
+        (Some(moov), Some(_)) => moov,
+        _ => {
+            // A box that claims more bytes than there are means the video was cut off
+            let cut_off = matches!(buf.get(end + 4..end + 8), Some(b"moov") | Some(b"mdat"));
+            return Err(invalid(if cut_off {
+                "The video is truncated"
+            } else {
+                "The video is missing its moov or mdat box"
+            }));
+        }
+    };
+
+    let mut offsets = Vec::new();
+    chunk_offsets(buf, moov, &mut offsets)
+        .ok_or_else(|| invalid("The sample tables of the video are corrupt"))?;
+    if offsets.iter().any(|chunk| chunk.offset >= end as u64) {
+        return Err(invalid(
+            "The video is truncated, its samples run past the end",
+        ));
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isobmff::bmff;
+
+    const SAMPLES: &[u8] = b"samples";
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    /// A sample table with a single chunk at `offset`, stored as `co64` when `large`.
+    fn stbl(offset: u64, large: bool) -> Vec<u8> {
+        let mut table = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        if large {
+            table.extend_from_slice(&offset.to_be_bytes());
+            bmff(b"stbl", &bmff(b"co64", &table))
+        } else {
+            table.extend_from_slice(&(offset as u32).to_be_bytes());
+            bmff(b"stbl", &bmff(b"stco", &table))
+        }
+    }
+
+    fn moov(offset: u64, large: bool, udta: Option<Vec<u8>>) -> Vec<u8> {
+        let mut mvhd = vec![0; 4];
+        mvhd.extend_from_slice(&3705393600u32.to_be_bytes());
+        mvhd.extend_from_slice(&3705393600u32.to_be_bytes());
+        let trak = bmff(
+            b"trak",
+            &bmff(b"mdia", &bmff(b"minf", &stbl(offset, large))),
+        );
+        let mut moov = bmff(b"mvhd", &mvhd);
+        moov.extend(trak);
+        moov.extend(udta.unwrap_or_default());
+        bmff(b"moov", &moov)
+    }
+
+    /// A movie with its header in front of the samples, as cameras write them.
+    fn movie(large: bool, udta: Option<Vec<u8>>) -> Vec<u8> {
+        let mut buf = bmff(b"ftyp", b"isom\0\0\0\0");
+        let header_len = moov(0, large, udta.clone()).len();
+        let offset = (buf.len() + header_len + 8) as u64;
+        buf.extend(moov(offset, large, udta));
+        buf.extend(bmff(b"mdat", SAMPLES));
+        buf
+    }
+
+    /// The samples the first chunk offset of `buf` points at.
+    fn samples(buf: &[u8]) -> &[u8] {
+        let moov = isobmff::find_box(buf, 0..buf.len(), b"moov").unwrap();
+        let mut offsets = Vec::new();
+        chunk_offsets(buf, &moov, &mut offsets).unwrap();
+        let offset = offsets[0].offset as usize;
+        &buf[offset..offset + SAMPLES.len()]
+    }
+
+    fn amsterdam() -> Metadata {
+        Metadata {
+            date: None,
+            location: Some(Location {
+                latitude: 52.3676,
+                longitude: 4.9041,
+                altitude: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn reads_the_creation_time() {
+        assert_eq!(
+            creation_time(&movie(false, None)),
+            Some(time("2021-06-01 12:00:00"))
+        );
+        assert_eq!(
+            creation_time(&bmff(b"moov", &bmff(b"mvhd", &[0; 12]))),
+            None
+        );
+    }
+
+    #[test]
+    fn writes_the_date_into_the_header() {
+        let buf = movie(false, None);
+        let metadata = Metadata {
+            date: Some(time("2020-01-02 03:04:05")),
+            location: None,
+        };
+        let dated = with_metadata(&buf, &metadata).unwrap();
+        assert_eq!(dated.len(), buf.len());
+        assert_eq!(creation_time(&dated), Some(time("2020-01-02 03:04:05")));
+        // Before 1904 doesn't fit
+        let metadata = Metadata {
+            date: Some(time("1900-01-01 00:00:00")),
+            location: None,
+        };
+        assert!(with_metadata(&buf, &metadata).is_none());
+    }
+
+    #[test]
+    fn adds_the_location_and_moves_the_samples_along() {
+        for large in [false, true] {
+            let buf = movie(large, None);
+            let located = with_metadata(&buf, &amsterdam()).unwrap();
+            assert_eq!(samples(&located), SAMPLES);
+            let moov = isobmff::find_box(&located, 0..located.len(), b"moov").unwrap();
+            let udta = isobmff::find_box(&located, moov.payload(), b"udta").unwrap();
+            let xyz = isobmff::find_box(&located, udta.payload(), b"\xA9xyz").unwrap();
+            assert_eq!(
+                &located[xyz.payload().start + 4..xyz.end],
+                b"+52.3676+004.9041/"
+            );
+            assert!(mp4_is_valid(&located));
+        }
+    }
+
+    #[test]
+    fn adds_the_location_to_existing_user_data() {
+        let buf = movie(false, Some(bmff(b"udta", &bmff(b"name", b"clip"))));
+        let located = with_metadata(&buf, &amsterdam()).unwrap();
+        assert_eq!(samples(&located), SAMPLES);
+        let moov = isobmff::find_box(&located, 0..located.len(), b"moov").unwrap();
+        let udta = isobmff::find_box(&located, moov.payload(), b"udta").unwrap();
+        let kinds: Vec<[u8; 4]> = isobmff::children(&located, &udta)
+            .map(|child| child.kind)
+            .collect();
+        assert_eq!(kinds, vec![*b"name", *b"\xA9xyz"]);
+    }
+
+    #[test]
+    fn keeps_an_existing_location() {
+        let mut xyz = vec![0, 4, 0x55, 0xC4];
+        xyz.extend_from_slice(b"+10.0+010.0/");
+        let buf = movie(false, Some(bmff(b"udta", &bmff(b"\xA9xyz", &xyz))));
+        assert_eq!(with_metadata(&buf, &amsterdam()).unwrap(), buf);
+    }
+
+    #[test]
+    fn leaves_offsets_in_front_of_the_insertion_alone() {
+        // The samples come first, so growing the header doesn't move them
+        let mut buf = bmff(b"ftyp", b"isom\0\0\0\0");
+        let offset = (buf.len() + 8) as u64;
+        buf.extend(bmff(b"mdat", SAMPLES));
+        buf.extend(moov(offset, false, None));
+        let located = with_metadata(&buf, &amsterdam()).unwrap();
+        assert_eq!(samples(&located), SAMPLES);
+        assert!(mp4_is_valid(&located));
+    }
+
+    #[test]
+    fn validates_a_complete_movie() {
+        let buf = movie(false, None);
+        assert_eq!(validate(&buf).unwrap(), buf.len());
+        // What follows the movie isn't part of it
+        let mut trailed = buf.clone();
+        trailed.extend_from_slice(b"\0\0SEFHjunk");
+        assert_eq!(validate(&trailed).unwrap(), buf.len());
+    }
+
+    #[test]
+    fn rejects_incomplete_movies() {
+        let message = |buf: &[u8]| validate(buf).unwrap_err().to_string();
+        let buf = movie(false, None);
+        assert_eq!(
+            message(&buf[16..]),
+            "The video doesn't start with a ftyp box"
+        );
+
+        let mdat = buf.len() - SAMPLES.len() - 8;
+        assert_eq!(
+            message(&buf[..mdat]),
+            "The video is missing its moov or mdat box"
+        );
+        assert_eq!(message(&buf[..buf.len() - 2]), "The video is truncated");
+
+        // The sample table points past the samples that are there
+        let mut short = buf[..mdat].to_vec();
+        short.extend(bmff(b"mdat", b""));
+        assert_eq!(
+            message(&short),
+            "The video is truncated, its samples run past the end"
+        );
+    }
+
+    fn mp4_is_valid(buf: &[u8]) -> bool {
+        validate(buf).ok() == Some(buf.len())
+    }
+}
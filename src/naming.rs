@@ -0,0 +1,153 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+
+/// A file name pattern like `{date}_{name}`, in which `{date}` is the date the photo was taken
+/// and `{name}` the original file name without its extension. The extension is kept as it is.
+/// Path separators in the template become underscores, so the files stay where they belong.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    pub const DEFAULT: &'static str = "{date}_{name}";
+
+    /// Falls back to the default for an empty template.
+    pub fn parse(template: &str) -> Self {
+        match template.trim() {
+            "" => Self::default(),
+            template => Self(template.to_string()),
+        }
+    }
+
+    /// File names don't have to be valid UTF-8, so the name is spliced in as it is.
+    pub fn render(&self, date: &str, name: &OsStr) -> OsString {
+        let mut rendered = OsString::new();
+        for (idx, part) in self.0.split("{name}").enumerate() {
+            if idx > 0 {
+                rendered.push(name);
+            }
+            rendered.push(part.replace("{date}", date).replace(['/', '\\'], "_"));
+        }
+        rendered
+    }
+
+    /// The new name of the file at `path`, with its extension kept. `None` if that isn't a
+    /// name a file can have.
+    pub fn rename(&self, date: &str, path: &Path) -> Option<OsString> {
+        let mut name = self.render(date, path.file_stem()?);
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        Some(name).filter(|name| !["", ".", ".."].iter().any(|invalid| name == invalid))
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl Display for NameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How the video extracted from a motion photo is named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VideoNaming {
+    /// `<photo>-motion.mp4`, after the photo as it was written
+    Motion,
+    /// The name template, applied to the original name of the photo
+    Template,
+    /// Exactly the name of the photo as it was written, like the video of a Live Photo
+    MatchPhoto,
+}
+
+impl VideoNaming {
+    pub const ALL: [VideoNaming; 3] = [Self::Motion, Self::Template, Self::MatchPhoto];
+}
+
+impl Default for VideoNaming {
+    fn default() -> Self {
+        Self::Motion
+    }
+}
+
+/// The folder for extracted videos as a path below the photo, leaving out anything that would
+/// lead elsewhere, like `..` or a root.
+pub fn video_folder(folder: &str) -> PathBuf {
+    Path::new(folder)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Display for VideoNaming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Motion => "Photo name with -motion",
+                Self::Template => "Name template",
+                Self::MatchPhoto => "Same as the photo",
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(template: &str, path: &str) -> Option<String> {
+        NameTemplate::parse(template)
+            .rename("2020-01-02", Path::new(path))
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn renames_with_the_template() {
+        assert_eq!(
+            rename("", "a/IMG_1.jpg").as_deref(),
+            Some("2020-01-02_IMG_1.jpg")
+        );
+        assert_eq!(
+            rename("{name} ({date}) {name}", "IMG_1.jpg").as_deref(),
+            Some("IMG_1 (2020-01-02) IMG_1.jpg")
+        );
+        assert_eq!(rename("{date}", "README").as_deref(), Some("2020-01-02"));
+        assert_eq!(rename("..", "README"), None);
+    }
+
+    #[test]
+    fn keeps_files_in_their_folder() {
+        assert_eq!(
+            rename("{date}/{name}", "IMG_1.jpg").as_deref(),
+            Some("2020-01-02_IMG_1.jpg")
+        );
+        assert_eq!(
+            rename("../../{name}", "IMG_1.jpg").as_deref(),
+            Some(".._.._IMG_1.jpg")
+        );
+        assert_eq!(
+            rename("..\\{name}", "IMG_1.jpg").as_deref(),
+            Some(".._IMG_1.jpg")
+        );
+        assert_eq!(rename(".", "README"), None);
+    }
+
+    #[test]
+    fn keeps_the_video_folder_below_the_photo() {
+        assert_eq!(video_folder(""), PathBuf::new());
+        assert_eq!(video_folder("videos/motion"), Path::new("videos/motion"));
+        assert_eq!(video_folder("../videos"), Path::new("videos"));
+        assert_eq!(video_folder("/tmp/./videos/.."), Path::new("tmp/videos"));
+    }
+}
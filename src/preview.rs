@@ -0,0 +1,30 @@
+use std::fs::read;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use crate::date::thumbnail;
+use crate::extract::{find_motion, Motion};
+
+/// What there is to show of a single photo.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    /// A JPEG: the thumbnail from the EXIF, or else the photo itself if it's a JPEG
+    pub image: Option<Vec<u8>>,
+    pub motion: Option<Motion>,
+}
+
+impl Preview {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let buf = read(path)?;
+        let motion = find_motion(&buf);
+        let exif = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(&buf))
+            .ok();
+        let image = match exif.as_ref().and_then(thumbnail) {
+            Some(thumbnail) => Some(thumbnail.to_vec()),
+            None if crate::jpeg::is_jpeg(&buf) => Some(buf),
+            None => None,
+        };
+        Ok(Self { image, motion })
+    }
+}
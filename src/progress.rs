@@ -0,0 +1,202 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scanning,
+    Hashing,
+    Planning,
+    Writing,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Scanning => "Scanning",
+                Self::Hashing => "Hashing",
+                Self::Planning => "Planning",
+                Self::Writing => "Writing",
+            }
+        )
+    }
+}
+
+/// A snapshot of how far the task is within its current phase. While scanning, the totals are
+/// not known yet and stay at 0.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub phase: Phase,
+    pub path: PathBuf,
+    pub done: u32,
+    pub total: u32,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// How many of the files found so far have motion data, only counted while scanning for
+    /// motion photos alone
+    pub motion_photos: Option<u32>,
+}
+
+/// Keeps track of when the current phase started, to derive the throughput and ETA from the
+/// progress updates of the task.
+pub struct Tracker {
+    progress: Progress,
+    phase_started: Instant,
+}
+
+impl Tracker {
+    pub fn new(progress: Progress) -> Self {
+        Self {
+            progress,
+            phase_started: Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, progress: Progress) {
+        if progress.phase != self.progress.phase {
+            self.phase_started = Instant::now();
+        }
+        self.progress = progress;
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// The fraction of the current phase that is done, between 0 and 1.
+    pub fn fraction(&self) -> f32 {
+        let progress = &self.progress;
+        if progress.bytes_total > 0 {
+            progress.bytes_done as f32 / progress.bytes_total as f32
+        } else if progress.total > 0 {
+            progress.done as f32 / progress.total as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// Bytes per second processed in the current phase.
+    pub fn throughput(&self) -> Option<f64> {
+        let elapsed = self.phase_started.elapsed().as_secs_f64();
+        if elapsed > 0.0 && self.progress.bytes_done > 0 {
+            Some(self.progress.bytes_done as f64 / elapsed)
+        } else {
+            None
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self
+            .progress
+            .bytes_total
+            .saturating_sub(self.progress.bytes_done);
+        self.throughput()
+            .map(|throughput| Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+}
+
+impl Display for Tracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let progress = &self.progress;
+        if progress.total == 0 {
+            write!(f, "{}: {} files found", progress.phase, progress.done)?;
+            if let Some(motion_photos) = progress.motion_photos {
+                write!(f, ", {} with motion data", motion_photos)?;
+            }
+            return Ok(());
+        }
+        write!(
+            f,
+            "{}: {}/{} ({}/{})",
+            progress.phase,
+            progress.done,
+            progress.total,
+            format_bytes(progress.bytes_done as f64),
+            format_bytes(progress.bytes_total as f64)
+        )?;
+        if let Some(throughput) = self.throughput() {
+            write!(f, ", {}/s", format_bytes(throughput))?;
+        }
+        if let Some(eta) = self.eta() {
+            let secs = eta.as_secs();
+            write!(f, ", ETA {}m {:02}s", secs / 60, secs % 60)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(
+        phase: Phase,
+        done: u32,
+        total: u32,
+        bytes_done: u64,
+        bytes_total: u64,
+    ) -> Progress {
+        Progress {
+            phase,
+            path: PathBuf::from("a.jpg"),
+            done,
+            total,
+            bytes_done,
+            bytes_total,
+            motion_photos: None,
+        }
+    }
+
+    #[test]
+    fn formats_bytes_in_the_largest_unit() {
+        assert_eq!(format_bytes(512.0), "512.0 B");
+        assert_eq!(format_bytes(1536.0), "1.5 KB");
+        assert_eq!(format_bytes(3.0 * 1024.0 * 1024.0 * 1024.0), "3.0 GB");
+        assert_eq!(format_bytes(2048.0 * 1024.0f64.powi(4)), "2048.0 TB");
+    }
+
+    #[test]
+    fn prefers_bytes_over_files_for_the_fraction() {
+        let mut tracker = Tracker::new(progress(Phase::Hashing, 1, 4, 300, 400));
+        assert_eq!(tracker.fraction(), 0.75);
+        tracker.update(progress(Phase::Planning, 1, 4, 0, 0));
+        assert_eq!(tracker.fraction(), 0.25);
+        tracker.update(progress(Phase::Scanning, 10, 0, 0, 0));
+        assert_eq!(tracker.fraction(), 0.0);
+    }
+
+    #[test]
+    fn counts_files_while_scanning() {
+        let mut scanning = progress(Phase::Scanning, 10, 0, 0, 0);
+        assert_eq!(
+            Tracker::new(scanning.clone()).to_string(),
+            "Scanning: 10 files found"
+        );
+        scanning.motion_photos = Some(3);
+        assert_eq!(
+            Tracker::new(scanning).to_string(),
+            "Scanning: 10 files found, 3 with motion data"
+        );
+    }
+
+    #[test]
+    fn shows_the_totals_of_the_phase() {
+        let tracker = Tracker::new(progress(Phase::Writing, 2, 5, 0, 2048));
+        assert_eq!(tracker.to_string(), "Writing: 2/5 (0.0 B/2.0 KB)");
+        assert!(tracker.eta().is_none());
+    }
+}
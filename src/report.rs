@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::write;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::extract::MotionFormat;
+
+const SUMMARY_NAME: &str = "motionsplit-summary";
+
+/// What a run did, shown once the task finishes and written into the destination.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    pub files_scanned: u32,
+    pub other_media: u32,
+    pub motion_photos: BTreeMap<MotionFormat, u32>,
+    pub live_photos: u32,
+    pub videos_extracted: u32,
+    pub converted: u32,
+    /// Without a date they weren't copied, and the originals are left alone
+    pub not_converted: u32,
+    pub exif_dates_written: u32,
+    pub dates_shifted: u32,
+    pub renamed: u32,
+    pub duplicates_skipped: u32,
+    pub filtered_out: u32,
+    pub excluded: u32,
+    pub resumed: u32,
+    pub errors: u32,
+}
+
+impl Summary {
+    pub fn motion_photo_count(&self) -> u32 {
+        self.motion_photos.values().sum()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("# MotionSplit summary\n\n| | |\n|---|---:|\n");
+        let mut row =
+            |name: &str, count: u32| markdown.push_str(&format!("| {} | {} |\n", name, count));
+        row("Files scanned", self.files_scanned);
+        row("Videos and other media", self.other_media);
+        row("Photos with motion data", self.motion_photo_count());
+        for (format, count) in &self.motion_photos {
+            row(&format!("Photos with motion data ({})", format), *count);
+        }
+        row("Live Photos paired", self.live_photos);
+        row("Videos extracted", self.videos_extracted);
+        row("Motion photos converted", self.converted);
+        row("Not converted, as they had no date", self.not_converted);
+        row("EXIF dates written", self.exif_dates_written);
+        row("Dates shifted", self.dates_shifted);
+        row("Files renamed", self.renamed);
+        row("Duplicates skipped", self.duplicates_skipped);
+        row("Left out by the filters", self.filtered_out);
+        row("Left out in the review", self.excluded);
+        row("Already done by an earlier run", self.resumed);
+        row("Errors", self.errors);
+        markdown
+    }
+
+    /// Writes the summary as both JSON and Markdown into `dir`.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write(dir.join(format!("{}.json", SUMMARY_NAME)), json)?;
+        write(dir.join(format!("{}.md", SUMMARY_NAME)), self.to_markdown())
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files scanned, {} with motion data",
+            self.files_scanned,
+            self.motion_photo_count()
+        )?;
+        if self.other_media > 0 {
+            write!(f, ", {} videos and other media", self.other_media)?;
+        }
+        if !self.motion_photos.is_empty() {
+            let formats: Vec<String> = self
+                .motion_photos
+                .iter()
+                .map(|(format, count)| format!("{}: {}", format, count))
+                .collect();
+            write!(f, " ({})", formats.join(", "))?;
+        }
+        if self.live_photos > 0 {
+            write!(f, ", {} Live Photos", self.live_photos)?;
+        }
+        write!(
+            f,
+            ", {} videos extracted, {} renamed, {} duplicates skipped",
+            self.videos_extracted, self.renamed, self.duplicates_skipped
+        )?;
+        if self.converted > 0 {
+            write!(f, ", {} converted", self.converted)?;
+        }
+        if self.not_converted > 0 {
+            write!(
+                f,
+                ", {} not converted for lack of a date",
+                self.not_converted
+            )?;
+        }
+        if self.exif_dates_written > 0 {
+            write!(f, ", {} EXIF dates written", self.exif_dates_written)?;
+        }
+        if self.dates_shifted > 0 {
+            write!(f, ", {} dates shifted", self.dates_shifted)?;
+        }
+        if self.filtered_out > 0 {
+            write!(f, ", {} left out by the filters", self.filtered_out)?;
+        }
+        if self.excluded > 0 {
+            write!(f, ", {} left out in the review", self.excluded)?;
+        }
+        if self.resumed > 0 {
+            write!(f, ", {} already done", self.resumed)?;
+        }
+        write!(f, ", {} errors", self.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> Summary {
+        let mut summary = Summary {
+            files_scanned: 12,
+            renamed: 5,
+            errors: 1,
+            ..Summary::default()
+        };
+        summary.motion_photos.insert(MotionFormat::Google, 2);
+        summary.motion_photos.insert(MotionFormat::Samsung, 1);
+        summary
+    }
+
+    #[test]
+    fn leaves_out_what_did_not_happen() {
+        assert_eq!(
+            summary().to_string(),
+            "12 files scanned, 3 with motion data (Samsung: 1, Google: 2), 0 videos extracted, \
+             5 renamed, 0 duplicates skipped, 1 errors"
+        );
+    }
+
+    #[test]
+    fn lists_every_count_in_markdown() {
+        let markdown = summary().to_markdown();
+        assert!(markdown
+            .starts_with("# MotionSplit summary\n\n| | |\n|---|---:|\n| Files scanned | 12 |\n"));
+        assert!(markdown.contains("| Photos with motion data | 3 |\n"));
+        assert!(markdown.contains("| Photos with motion data (Samsung) | 1 |\n"));
+        assert!(markdown.contains("| Photos with motion data (Google) | 2 |\n"));
+        assert!(markdown.contains("| Dates shifted | 0 |\n"));
+        assert!(markdown.ends_with("| Errors | 1 |\n"));
+    }
+
+    #[test]
+    fn writes_json_and_markdown() {
+        let dir = std::env::temp_dir().join(format!("motionsplit-report-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        summary().write(&dir).unwrap();
+
+        let json = std::fs::read_to_string(dir.join("motionsplit-summary.json")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["files_scanned"], 12);
+        assert_eq!(json["motion_photos"]["Google"], 2);
+        let markdown = std::fs::read_to_string(dir.join("motionsplit-summary.md")).unwrap();
+        assert_eq!(markdown, summary().to_markdown());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use chrono::NaiveDate;
+
+use crate::date::DateSource;
+use crate::extract::MotionFormat;
+
+/// One of the identical copies of a planned file.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Relative to the input
+    pub source: PathBuf,
+    pub size: u64,
+    pub date: Option<String>,
+    pub date_source: Option<DateSource>,
+    pub modified: Option<String>,
+}
+
+/// A planned file, as shown for review before anything is written.
+#[derive(Debug, Clone)]
+pub struct Row {
+    /// The copy that was picked comes first, followed by the ones skipped in its favour
+    pub candidates: Vec<Candidate>,
+    pub motion: Option<MotionFormat>,
+    pub live_video: bool,
+}
+
+/// What the user made of a row.
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub include: bool,
+    /// Which of the candidates is written
+    pub keep: usize,
+    /// Replaces the date that was found
+    pub date: Option<NaiveDate>,
+}
+
+/// A decision for each row in order, or `None` to stop before anything is written.
+pub type Reply = Sender<Option<Vec<Decision>>>;
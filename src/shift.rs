@@ -0,0 +1,234 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use exif::Exif;
+
+use crate::filter::camera_name;
+
+/// No clock is off by more than this, and it keeps shifted dates well within what can be stored.
+const MAX_YEARS: i32 = 1000;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Which photos a shift applies to.
+#[derive(Debug, Clone)]
+enum Scope {
+    All,
+    /// Matched against the make and model together, ignoring case
+    Camera(String),
+    /// A folder inside the input the photo was found in, along with everything below it
+    Folder(PathBuf),
+}
+
+/// Corrects the dates of photos taken by a camera whose clock was off, like `-1y +1h`.
+#[derive(Debug, Clone)]
+pub struct TimeShift {
+    source: String,
+    scope: Scope,
+    years: i32,
+    seconds: i64,
+}
+
+impl TimeShift {
+    /// Parses a rule like `-1y +1h`, `camera Canon EOS 80D: -1y +1h` or `folder trips/2019: +2h`.
+    /// The offset is made of whole numbers of years (`y`), days (`d`), hours (`h`), minutes (`m`)
+    /// and seconds (`s`), separated by spaces, adding up to at most a thousand years.
+    fn parse(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        let (scope, offset) = match source.rsplit_once(':') {
+            Some((scope, offset)) => {
+                let scope = scope.trim();
+                let scope = if let Some(camera) = strip_keyword(scope, "camera") {
+                    Scope::Camera(camera.to_lowercase())
+                } else if let Some(folder) = strip_keyword(scope, "folder") {
+                    Scope::Folder(PathBuf::from(folder))
+                } else {
+                    return Err(format!(
+                        "{} should start with camera or folder, like camera Canon EOS: -1h",
+                        source
+                    ));
+                };
+                (scope, offset)
+            }
+            None => (Scope::All, source),
+        };
+
+        let invalid = || format!("{} is not a time shift like -1y +1h", source);
+        let (mut years, mut seconds) = (0i32, 0i64);
+        for part in offset.split_whitespace() {
+            let unit_at = part.char_indices().last().map_or(0, |(idx, _)| idx);
+            let (number, unit) = part.split_at(unit_at);
+            let number: i64 = number.parse().map_err(|_| invalid())?;
+            let unit = match unit {
+                "y" => {
+                    let number = i32::try_from(number).map_err(|_| invalid())?;
+                    years = years.checked_add(number).ok_or_else(invalid)?;
+                    continue;
+                }
+                "d" => SECONDS_PER_DAY,
+                "h" => 60 * 60,
+                "m" => 60,
+                "s" => 1,
+                _ => return Err(invalid()),
+            };
+            seconds = number
+                .checked_mul(unit)
+                .and_then(|part| seconds.checked_add(part))
+                .ok_or_else(invalid)?;
+        }
+        if years == 0 && seconds == 0 {
+            return Err(invalid());
+        }
+        let max_seconds = MAX_YEARS as i64 * 366 * SECONDS_PER_DAY;
+        if years.abs() > MAX_YEARS || seconds.abs() > max_seconds {
+            return Err(format!(
+                "{} shifts by more than {} years",
+                source, MAX_YEARS
+            ));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            scope,
+            years,
+            seconds,
+        })
+    }
+
+    fn applies_to(&self, relative: &Path, exif: Option<&Exif>) -> bool {
+        match &self.scope {
+            Scope::All => true,
+            Scope::Camera(camera) => camera_name(exif).contains(camera.as_str()),
+            Scope::Folder(folder) => relative.starts_with(folder),
+        }
+    }
+
+    /// Moves `time` by the offset. A 29th of February that doesn't exist in the shifted year
+    /// becomes the 28th. Returns `None` if the result can't be represented.
+    pub fn apply(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let year = time.year().checked_add(self.years)?;
+        let shifted = time
+            .with_year(year)
+            .or_else(|| (time - Duration::days(1)).with_year(year))?;
+        shifted.checked_add_signed(Duration::seconds(self.seconds))
+    }
+
+    /// Moves a day whose time is unknown. Only the years and whole days of the offset are applied,
+    /// as an hour or so could just as well move it to the day before as keep it where it is.
+    pub fn apply_to_date(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let shift = Self {
+            seconds: self.seconds / SECONDS_PER_DAY * SECONDS_PER_DAY,
+            ..self.clone()
+        };
+        shift.apply(date.and_hms(0, 0, 0)).map(|time| time.date())
+    }
+}
+
+/// `camera Canon` and `Camera Canon` both give `Canon`.
+fn strip_keyword<'a>(scope: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = scope.get(keyword.len()..)?;
+    if !scope[..keyword.len()].eq_ignore_ascii_case(keyword) || !rest.starts_with(' ') {
+        return None;
+    }
+    Some(rest.trim()).filter(|rest| !rest.is_empty())
+}
+
+/// The user's time shift rules. The first one that applies to a photo is the one it gets.
+#[derive(Debug, Clone, Default)]
+pub struct TimeShifts(Vec<TimeShift>);
+
+impl TimeShifts {
+    /// Parses semicolon separated rules.
+    pub fn parse(rules: &str) -> Result<Self, String> {
+        rules
+            .split(';')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(TimeShift::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// The shift for the photo at `relative`, a path relative to the input it was found in.
+    pub fn find(&self, relative: &Path, exif: Option<&Exif>) -> Option<&TimeShift> {
+        self.0.iter().find(|shift| shift.applies_to(relative, exif))
+    }
+}
+
+impl Display for TimeShifts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rules: Vec<&str> = self.0.iter().map(|shift| shift.source.as_str()).collect();
+        write!(f, "time_shifts=[{}]", rules.join("; "))
+    }
+}
+
+impl Hash for TimeShifts {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_string().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let shifts =
+            TimeShifts::parse("camera Canon EOS: -1y +1h; folder trips/2019: +2d").unwrap();
+        assert_eq!(shifts.0.len(), 2);
+        assert_eq!(shifts.0[0].years, -1);
+        assert_eq!(shifts.0[0].seconds, 3600);
+        assert!(shifts.find(Path::new("trips/2019/a.jpg"), None).is_some());
+        assert!(shifts.find(Path::new("trips/2020/a.jpg"), None).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_and_huge_shifts() {
+        for rule in ["", "+1w", "1", "lens X: +1h", "camera: +1h"] {
+            assert!(TimeShift::parse(rule).is_err(), "{}", rule);
+        }
+        for rule in [
+            "9000000000000000s",
+            "1001y",
+            "+400000d",
+            "9223372036854775807s 1s",
+        ] {
+            assert!(TimeShift::parse(rule).is_err(), "{}", rule);
+        }
+        assert!(TimeShift::parse("-1000y").is_ok());
+    }
+
+    #[test]
+    fn shifts_times() {
+        let shift = TimeShift::parse("-1y -1h").unwrap();
+        assert_eq!(
+            shift.apply(time("2020-01-01 00:30:00")),
+            Some(time("2018-12-31 23:30:00"))
+        );
+        let shift = TimeShift::parse("+1y").unwrap();
+        assert_eq!(
+            shift.apply(time("2020-02-29 12:00:00")),
+            Some(time("2021-02-28 12:00:00"))
+        );
+    }
+
+    #[test]
+    fn days_without_a_time_only_move_by_whole_days() {
+        let date = NaiveDate::from_ymd(2020, 6, 1);
+        let shift = TimeShift::parse("-1y -1h").unwrap();
+        assert_eq!(
+            shift.apply_to_date(date),
+            Some(NaiveDate::from_ymd(2019, 6, 1))
+        );
+        let shift = TimeShift::parse("-1d -1h").unwrap();
+        assert_eq!(
+            shift.apply_to_date(date),
+            Some(NaiveDate::from_ymd(2020, 5, 31))
+        );
+    }
+}
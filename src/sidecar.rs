@@ -0,0 +1,140 @@
+use std::fs::write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::date::DateSource;
+use crate::extract::Motion;
+
+/// Everything a run found out about a single file, as written into its JSON sidecar.
+#[derive(Debug, Serialize)]
+pub struct Analysis<'a> {
+    pub source: &'a Path,
+    pub date: Option<String>,
+    pub date_source: Option<DateSource>,
+    pub capture_time: Option<String>,
+    /// Only known when duplicates are filtered
+    pub sha256: Option<String>,
+    /// The copies of this file that were skipped in its favour
+    pub duplicates: &'a [&'a Path],
+    pub motion: Option<Motion>,
+    pub live_video: Option<&'a Path>,
+}
+
+/// `IMG_1.jpg` gets `IMG_1.jpg.xmp`, so files that only differ in their extension, like a photo
+/// and its video, don't end up sharing a sidecar. The JSON gets `IMG_1.jpg.motionsplit.json`, as
+/// `IMG_1.jpg.json` is where Google Takeout keeps its own.
+fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(ext);
+    path.with_file_name(name)
+}
+
+pub fn write_json(path: &Path, analysis: &Analysis) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(analysis)?;
+    write(sidecar_path(path, "motionsplit.json"), json)
+}
+
+/// Writes the date, in ISO 8601, into the tags photo managers read it from.
+pub fn write_xmp(path: &Path, date: &str) -> io::Result<()> {
+    let xmp = format!(
+        r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="MotionSplit">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""
+        xmlns:exif="http://ns.adobe.com/exif/1.0/"
+        xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+        xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+      exif:DateTimeOriginal="{date}"
+      xmp:CreateDate="{date}"
+      photoshop:DateCreated="{date}"/>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#,
+        date = date
+    );
+    write(sidecar_path(path, "xmp"), xmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+
+    use super::*;
+    use crate::extract::MotionFormat;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "motionsplit-sidecar-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn names_sidecars_after_the_whole_file_name() {
+        assert_eq!(
+            sidecar_path(Path::new("a/IMG_1.jpg"), "xmp"),
+            Path::new("a/IMG_1.jpg.xmp")
+        );
+        assert_eq!(
+            sidecar_path(Path::new("IMG_1.mp4"), "motionsplit.json"),
+            Path::new("IMG_1.mp4.motionsplit.json")
+        );
+    }
+
+    #[test]
+    fn writes_the_analysis_as_json() {
+        let dir = dir("json");
+        let photo = dir.join("IMG_1.jpg");
+        let duplicates = [Path::new("b/IMG_1.jpg")];
+        let analysis = Analysis {
+            source: Path::new("a/IMG_1.jpg"),
+            date: Some("2020-01-02".into()),
+            date_source: Some(DateSource::Exif),
+            capture_time: Some("2020-01-02T03:04:05+01:00".into()),
+            sha256: None,
+            duplicates: &duplicates,
+            motion: Some(Motion {
+                format: MotionFormat::Google,
+                offset: 100,
+                length: 50,
+                presentation_timestamp_us: Some(1500),
+            }),
+            live_video: None,
+        };
+        write_json(&photo, &analysis).unwrap();
+
+        let json = read_to_string(dir.join("IMG_1.jpg.motionsplit.json")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["source"], "a/IMG_1.jpg");
+        assert_eq!(json["date_source"], "Exif");
+        assert_eq!(json["duplicates"][0], "b/IMG_1.jpg");
+        assert_eq!(json["motion"]["format"], "Google");
+        assert_eq!(json["motion"]["presentation_timestamp_us"], 1500);
+        assert!(json["sha256"].is_null());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_the_date_into_every_tag() {
+        let dir = dir("xmp");
+        write_xmp(&dir.join("IMG_1.jpg"), "2020-01-02T03:04:05Z").unwrap();
+        let xmp = read_to_string(dir.join("IMG_1.jpg.xmp")).unwrap();
+        for tag in [
+            "exif:DateTimeOriginal",
+            "xmp:CreateDate",
+            "photoshop:DateCreated",
+        ] {
+            assert!(xmp.contains(&format!(r#"{}="2020-01-02T03:04:05Z""#, tag)));
+        }
+        assert!(xmp.starts_with("<?xpacket begin="));
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::read;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use regex::Regex;
+use serde_json::Value;
+
+/// Takeout cuts the names of its JSON files off at this many characters, before `.json`.
+const MAX_NAME_LEN: usize = 46;
+const SUPPLEMENTAL: &str = ".supplemental-metadata";
+
+/// The names Google Takeout may have given the JSON file describing the photo at `path`. For
+/// `IMG_1(1)-edited.jpg` these include `IMG_1.jpg(1).json` and
+/// `IMG_1.jpg.supplemental-metadata(1).json`, each cut off if the name gets too long.
+fn candidates(path: &Path) -> Vec<PathBuf> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Vec::new(),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot..]),
+        None => (&name[..], ""),
+    };
+    // Edited copies share the JSON of the original. Takeout names them in the language of the
+    // account, like `-edited`, `-bearbeitet` or `-modifié`, so a word after a dash is left out
+    // once the name as it is has been tried.
+    let edited = Regex::new(r"^(.+?)-\p{L}+$").unwrap();
+    let original = edited
+        .captures(stem)
+        .map(|captures| captures.get(1).unwrap().as_str());
+    // Duplicate names get a counter, which Takeout moves to the end of the JSON name
+    let counter = Regex::new(r"^(.*?)(\(\d+\))$").unwrap();
+    let truncate = |name: String| -> String { name.chars().take(MAX_NAME_LEN).collect() };
+
+    let mut candidates = Vec::new();
+    for stem in std::iter::once(stem).chain(original) {
+        let (stem, counter) = match counter.captures(stem) {
+            Some(captures) => (
+                captures.get(1).unwrap().as_str(),
+                captures.get(2).unwrap().as_str(),
+            ),
+            None => (stem, ""),
+        };
+        for candidate in [
+            truncate(format!("{}{}{}", stem, ext, SUPPLEMENTAL)),
+            truncate(format!("{}{}", stem, ext)),
+            truncate(stem.to_string()),
+        ] {
+            let candidate = path.with_file_name(format!("{}{}.json", candidate, counter));
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// When the photo at `path` was taken according to its Takeout JSON, in UTC.
+pub fn taken_time(path: &Path) -> Option<NaiveDateTime> {
+    candidates(path)
+        .iter()
+        .filter(|candidate| candidate.is_file())
+        .find_map(|candidate| parse(&read(candidate).ok()?))
+}
+
+fn parse(json: &[u8]) -> Option<NaiveDateTime> {
+    let json: Value = serde_json::from_slice(json).ok()?;
+    let timestamp = &json.get("photoTakenTime")?["timestamp"];
+    // Takeout writes the timestamp as a string, but accept a number all the same
+    let seconds = match timestamp {
+        Value::String(seconds) => seconds.parse().ok()?,
+        Value::Number(seconds) => seconds.as_i64()?,
+        _ => return None,
+    };
+    // Photos without a known date get the epoch
+    if seconds <= 0 {
+        return None;
+    }
+    NaiveDateTime::from_timestamp_opt(seconds, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(path: &str) -> Vec<String> {
+        candidates(Path::new(path))
+            .iter()
+            .map(|candidate| {
+                candidate
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tries_the_supplemental_name_first() {
+        assert_eq!(
+            names("a/IMG_1.jpg"),
+            [
+                "IMG_1.jpg.supplemental-metadata.json",
+                "IMG_1.jpg.json",
+                "IMG_1.json"
+            ]
+        );
+        assert_eq!(
+            candidates(Path::new("a/IMG_1.jpg"))[1],
+            Path::new("a/IMG_1.jpg.json")
+        );
+    }
+
+    #[test]
+    fn moves_the_counter_to_the_end() {
+        assert_eq!(names("IMG_1(2).jpg")[1], "IMG_1.jpg(2).json");
+        assert_eq!(
+            names("IMG_1(1)-edited.jpg")[3..],
+            [
+                "IMG_1.jpg.supplemental-metadata(1).json",
+                "IMG_1.jpg(1).json",
+                "IMG_1(1).json"
+            ]
+        );
+    }
+
+    #[test]
+    fn shares_the_json_of_the_original_in_any_language() {
+        for edited in [
+            "IMG_1-edited.jpg",
+            "IMG_1-bearbeitet.jpg",
+            "IMG_1-modifié.jpg",
+            "IMG_1-editado.jpg",
+        ] {
+            assert!(
+                names(edited).contains(&"IMG_1.jpg.json".to_string()),
+                "{}",
+                edited
+            );
+        }
+        // The name as it is comes first, in case the dash was part of it
+        assert_eq!(names("Summer-holiday.jpg")[1], "Summer-holiday.jpg.json");
+        assert!(names("Summer-holiday.jpg").contains(&"Summer.jpg.json".to_string()));
+        // Numbers aren't a word Takeout adds
+        assert_eq!(names("IMG-20200101-WA0001.jpg").len(), 3);
+    }
+
+    #[test]
+    fn cuts_long_names_off() {
+        let long = format!("{}.jpg", "a".repeat(50));
+        assert_eq!(names(&long), [format!("{}.json", "a".repeat(MAX_NAME_LEN))]);
+        let name = "PXL_20210101_120000000.PORTRAIT.ORIGINAL.jpg";
+        assert_eq!(
+            names(name)[0],
+            "PXL_20210101_120000000.PORTRAIT.ORIGINAL.jpg.s.json"
+        );
+    }
+
+    #[test]
+    fn reads_the_time_the_photo_was_taken() {
+        let time = |json: &str| parse(json.as_bytes());
+        assert_eq!(
+            time(r#"{"photoTakenTime": {"timestamp": "1577934245"}}"#),
+            NaiveDateTime::from_timestamp_opt(1577934245, 0)
+        );
+        assert_eq!(
+            time(r#"{"photoTakenTime": {"timestamp": 1577934245}}"#),
+            NaiveDateTime::from_timestamp_opt(1577934245, 0)
+        );
+        assert_eq!(time(r#"{"photoTakenTime": {"timestamp": "0"}}"#), None);
+        assert_eq!(time(r#"{"creationTime": {"timestamp": "1"}}"#), None);
+    }
+}